@@ -0,0 +1,148 @@
+use std::fmt::Display;
+use crate::son::parser::Span;
+
+// NB: an error located in the source, rendered like rustc's:
+//     error: cannot find `x` in this scope
+//      --> tests/c0/lexical/if_scope.c:9:12
+//       |
+//     9 |     return x;
+//       |            ^
+#[derive(Debug)]
+pub struct Diagnostic { pub file: String, pub line: usize, pub col: usize, pub ident: String, pub msg: String, pub snippet: String }
+
+impl Diagnostic {
+    pub fn new(file: &str, chars: &[char], span: Span, ident: &str, msg: String) -> Self {
+        let src = chars.split(|c| *c == '\n').nth(span.line - 1).unwrap_or_default();
+        let gutter = " ".repeat(span.line.to_string().len());
+
+        // NB: tabs are kept in the caret's padding, so it lines up with the source however tabs are rendered
+        let pad = src.iter().take(span.col - 1).map(|c| if *c == '\t' { '\t' } else { ' ' }).collect::<String>();
+        let carets = "^".repeat(ident.chars().count().max(1));
+        let snippet = format!("{gutter} |\n{} | {}\n{gutter} | {pad}{carets}", span.line, src.iter().collect::<String>());
+        Self { file: file.to_owned(), line: span.line, col: span.col, ident: ident.to_owned(), msg, snippet }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "error: {}\n{}--> {}:{}:{}\n{}", self.msg, " ".repeat(self.line.to_string().len()), self.file, self.line, self.col, self.snippet)
+    }
+}
+
+#[cfg(test)]
+mod test_diagnostic {
+    use std::path::Path;
+    use crate::son::{optimizer::OptLevel, parser, utils::read_chars};
+
+    fn diagnose(file: &str) -> String {
+        let chars = read_chars(Path::new(file));
+        let errs = parser::parse(&chars, OptLevel::O1).err().expect("source is invalid");
+        errs.iter().map(|e| e.diagnose(file, &chars).to_string()).collect::<Vec<_>>().join("\n\n")
+    }
+
+    #[test] fn at() {
+        assert_eq!(diagnose("tests/c0/lexical/at.c"), "\
+error: unknown token `@`
+ --> tests/c0/lexical/at.c:2:13
+  |
+2 |     return 0@1;
+  |             ^");
+    }
+    #[test] fn id() {
+        assert_eq!(diagnose("tests/c0/lexical/id.c"), "\
+error: invalid literal `1foo`
+ --> tests/c0/lexical/id.c:2:12
+  |
+2 |     return 1foo;
+  |            ^^^^");
+    }
+    #[test] fn backslash() {
+        assert_eq!(diagnose("tests/c0/lexical/backslash.c"), "\
+error: unknown token `\\`
+ --> tests/c0/lexical/backslash.c:1:1
+  |
+1 | \\
+  | ^");
+    }
+    #[test] fn if_scope() {
+        assert_eq!(diagnose("tests/c0/lexical/if_scope.c"), "\
+error: cannot find `x` in this scope
+ --> tests/c0/lexical/if_scope.c:8:12
+  |
+8 |     return x;
+  |            ^");
+    }
+    #[test] fn recover() {
+        assert_eq!(diagnose("tests/c0/lexical/recover.c"), "\
+error: expected an expression, found `;`
+ --> tests/c0/lexical/recover.c:2:13
+  |
+2 |     int x = ;
+  |             ^
+
+error: cannot find `z` in this scope
+ --> tests/c0/lexical/recover.c:4:9
+  |
+4 |     y = z;
+  |         ^
+
+error: expected `;`, found `}`
+ --> tests/c0/lexical/recover.c:5:27
+  |
+5 |     if (y) { y = 2; x = 1 }
+  |                           ^
+
+error: expected an expression, found `;`
+ --> tests/c0/lexical/recover.c:6:15
+  |
+6 |     return y +;
+  |               ^
+
+error: expected `}`, found end of file
+ --> tests/c0/lexical/recover.c:6:16
+  |
+6 |     return y +;
+  |                ^");
+    }
+    #[test] fn for_header() {
+        // the unclosed header is reported at its step, and recovery resumes after the statement
+        assert_eq!(diagnose("tests/c0/lexical/for_header.c"), "\
+error: expected `)`, found `x`
+ --> tests/c0/lexical/for_header.c:3:33
+  |
+3 |     for (int i = 0; i < 10; i++ x += i;
+  |                                 ^
+
+error: cannot find `y` in this scope
+ --> tests/c0/lexical/for_header.c:4:12
+  |
+4 |     return y;
+  |            ^");
+    }
+    #[test] fn for_step() {
+        // the step is parsed after the body, but its errors are found where it's written
+        let chars = "int main() {\n    for (int i = 0; i < 2; i = ) {}\n    return 0;\n}".chars().collect::<Vec<_>>();
+        let errs = parser::parse(&chars, OptLevel::O1).err().expect("source is invalid");
+        assert_eq!(errs.iter().map(|e| e.diagnose("for.c", &chars).to_string()).collect::<Vec<_>>(), ["\
+error: expected an expression, found `)`
+ --> for.c:2:32
+  |
+2 |     for (int i = 0; i < 2; i = ) {}
+  |                                ^"]);
+    }
+    #[test] fn lex_errors() {
+        let chars = "int main() { return 0@1 + `2; }".chars().collect::<Vec<_>>();
+        let errs = parser::parse(&chars, OptLevel::O1).err().expect("source is invalid");
+        assert_eq!(errs.iter().map(|e| e.ident()).collect::<Vec<_>>(), ["@", "`"]);
+    }
+    #[test] fn unterminated_comment() {
+        let chars = "int main() {\n    /* return 0; */ /* return 1;\n}".chars().collect::<Vec<_>>();
+        let errs = parser::parse(&chars, OptLevel::O1).err().expect("source is invalid");
+        assert_eq!(errs.iter().map(|e| e.diagnose("comment.c", &chars).to_string()).collect::<Vec<_>>(), ["\
+error: unterminated block comment
+ --> comment.c:2:21
+  |
+2 |     /* return 0; */ /* return 1;
+  |                     ^^"]);
+    }
+}
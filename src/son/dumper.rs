@@ -1,5 +1,5 @@
-use std::{collections::{HashMap}, fmt::{self, Write}};
-use crate::son::{parser::{ParseResult, Scope}, DefEdge, OpCode};
+use std::fmt::{self, Write};
+use crate::son::{parser::{ParseResult, Scope}, Arena, NodeId, OpCode};
 
 pub fn dump_dot(char_input: &[char], graph_output: &ParseResult) -> Result<String, fmt::Error> {
     let mut dump = String::new();
@@ -8,36 +8,36 @@ pub fn dump_dot(char_input: &[char], graph_output: &ParseResult) -> Result<Strin
     write!(dump, "\trankdir=BT;\n")?; // force nodes before scopes
     write!(dump, "\tordering=\"in\";\n")?; // preserve node input order
     write!(dump, "\tconcentrate=\"true\";\n")?; // merge multiple edges hitting the same node
-    let flat_graph = flatten(graph_output);
+    let (arena, flat_graph) = (&graph_output.arena, flatten(graph_output));
 
-    dump_nodes(&mut dump, &flat_graph)?;
+    dump_nodes(&mut dump, arena, &flat_graph)?;
     dump_scope(&mut dump, &graph_output.scope)?;
-    dump_node_edges(&mut dump, &flat_graph)?;
+    dump_node_edges(&mut dump, arena, &flat_graph)?;
     // dump_scope_edges(&mut dump, &parser.scope)?;
     write!(dump, "}}\n")?;
     Ok(dump)
 }
 
-fn dump_nodes(s: &mut String, flat_graph: &Vec<DefEdge>) -> fmt::Result {
+fn dump_nodes(s: &mut String, arena: &Arena, flat_graph: &Vec<NodeId>) -> fmt::Result {
     write!(s, "\tsubgraph cluster_Nodes {{\n")?;
-    for node in flat_graph {
-        if let OpCode::Scope = node.borrow().opcode { continue }
-        write!(s, "\t\t{} [ ", node.unique_label())?;
-        if node.is_cfg() { write!(s, "shape=box style=filled fillcolor=yellow ")?; } // default is ellipse
-        write!(s, "label=\"{:?}\" ", node)?;
+    for &node in flat_graph {
+        if let OpCode::Scope = arena.op(node) { continue }
+        write!(s, "\t\t{} [ ", arena.unique_label(node))?;
+        if arena.is_cfg(node) { write!(s, "shape=box style=filled fillcolor=yellow ")?; } // default is ellipse
+        write!(s, "label=\"{:?}\" ", arena.at(node))?;
         write!(s, "];\n")?;
     }
     write!(s, "\t}}\n")
 }
-fn dump_node_edges(s: &mut String, flat_graph: &Vec<DefEdge>) -> fmt::Result {
+fn dump_node_edges(s: &mut String, arena: &Arena, flat_graph: &Vec<NodeId>) -> fmt::Result {
     write!(s, "\tedge [ fontname=Helvetica, fontsize=8 ];\n")?;
-    for node in flat_graph {
-        if let OpCode::Scope = node.borrow().opcode { continue }
-        for (i, def) in (&node.borrow().defs).iter().enumerate() {
-            write!(s, "\t{} -> {}", node.unique_label(), def.unique_label())?; // unique labels for DOT (display not enough)
+    for &node in flat_graph {
+        if let OpCode::Scope = arena.op(node) { continue }
+        for (i, &def) in arena.defs(node).iter().enumerate() {
+            write!(s, "\t{} -> {}", arena.unique_label(node), arena.unique_label(def))?; // unique labels for DOT (display not enough)
             write!(s, "[taillabel={i}")?;
-            if let (OpCode::Con, OpCode::Start) = (node.borrow().opcode, def.borrow().opcode) { write!(s, " style=dotted")?; }
-            else if def.is_cfg() { write!(s, " color=red")?; }
+            if let (OpCode::Con, OpCode::Start) = (arena.op(node), arena.op(def)) { write!(s, " style=dotted")?; }
+            else if arena.is_cfg(def) { write!(s, " color=red")?; }
             write!(s, "];\n")?;
         }
     }
@@ -64,27 +64,9 @@ fn dump_scope(s: &mut String, scope: &Scope) -> fmt::Result {
 }
 fn _dump_scope_edges(s: &mut String, scope: &Scope) -> fmt::Result { todo!() }
 
-fn flatten(parsed: &ParseResult) -> Vec<DefEdge> {
-    let mut seen = HashMap::new();
-    traverse_graph_from_node(&parsed.start, &mut seen);
-    traverse_scope_bindings(&parsed.scope, &mut seen);
-    seen.values().cloned().collect::<Vec<_>>()
-}
-
-fn traverse_scope_bindings(scope: &Scope, seen: &mut HashMap<usize, DefEdge>) -> () {
-    for nv in &scope.nvs {
-        for idef in nv.values() { // NB: linear in the size of bindings
-            let bound_expr = &scope.lookup.borrow().defs[*idef];
-            traverse_graph_from_node(bound_expr, seen)
-        }
-    }
-}
-
-fn traverse_graph_from_node(node: &DefEdge, seen: &mut HashMap<usize, DefEdge>) -> () {
-    if seen.contains_key(&node.borrow().id) { return } else { seen.insert(node.borrow().id, node.clone());}
-    for n in &node.borrow().defs { traverse_graph_from_node(node, seen); }
-    for n in &node.borrow().uses { traverse_graph_from_node(&DefEdge::from_upgraded(n.upgrade().unwrap()), seen) }
-}
+// NB: every node in the arena is live once parsing sweeps the garbage, including uncalled functions
+//     (which are not reachable from start) and the expressions still bound in the scope
+fn flatten(parsed: &ParseResult) -> Vec<NodeId> { parsed.arena.ids().collect() }
 
 // fn bfs(root: &DefEdge, max_depth: usize) -> (Vec<DefEdge>, Vec<bool>) {
 //     let (mut queue, mut seen) = (VecDeque::new(), Vec::new());
@@ -1,103 +1,248 @@
 // TODOs (day5)
 // - ir dump ascii/graphviz, then finish scope tests
 // - remove golden tests for graphs: the expected graph changes too often on peephole additions
-// - disable peephole (-O0)
 // - ssa
 pub mod generator;
 pub mod optimizer;
 pub mod parser;
+pub mod scheduler;
 pub mod dumper;
+pub mod diagnostic;
 pub mod utils;
 
-use std::{cell::RefCell, collections::VecDeque, fmt::{Debug, Display}, ops::Deref, rc::{Rc, Weak}};
-use thiserror::Error;
-use crate::son::optimizer::Type;
+use std::{collections::HashMap, fmt::{Debug, Display}, mem, num::NonZeroU32};
+use crate::son::optimizer::{GvnKey, Type};
 
-// NB: parallelizing the compiler requires moving the static mutable counter into TLS
-//     also skipping 0 since node ids show up in bit vectors, work lists, etc.
-static mut NODEID_COUNTER: usize = 0;
-pub fn generate_nodeid() -> usize { unsafe { NODEID_COUNTER += 1; NODEID_COUNTER } }
+// NB: nodes live in an arena owned by their graph, and are named by their index into it. ids skip 0
+//     since they show up in bit vectors, work lists, etc. (which also keeps an Option<NodeId> 4 bytes).
+//     freed ids are reused, so an id only names a node while it's alive
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)] pub struct NodeId(NonZeroU32);
+impl NodeId { pub fn index(self) -> usize { self.0.get() as usize } }
 
 // some code in simple relies on invariant that first edge is control.
 // this is removed for now so edge type is not optioned. watch out for this.
 // removed: matched on the opcode. if add/sub/etc set self.defs[0] to none}
 
 // TODO: is dynamic matching on opcode too slow vs dynamic dispatch with vtables (trait items) or static __ with generics?
-#[derive(Clone, Copy)] pub enum OpCode { Start, Ret, Con, Add, Sub, Mul, Div, Scope }
-pub struct Node { id: usize, pub opcode: OpCode, typ: Type, defs: VecDeque<DefEdge>, uses: VecDeque<UseEdge> }
-#[derive(Error, Debug)] pub enum NodeError { #[error("use not found")] UseNotFound }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)] pub enum OpCode { Start, Stop, Ret, Con, Add, Sub, Mul, Div, Mod, Neg, Not, Compl, Eq, Ne, Lt, Le, Scope, If, Never, Proj(usize), Region, Loop, Phi, Fun(usize), Parm(usize), Call(usize), CallEnd }
 // NB: - all nodes including control have types.
 //     - the order of defs has semantic meaning. order of uses does not.
 //       q: what's the point of maintaining D->U edges? (aka outputs/uses)
+//     a node's opcode and defs are stored together, while types and uses are side tables indexed by id,
+//     since passes (sccp, the scheduler) read and rewrite them apart from the rest of the node.
+//     every def records its position in the def's uses (slots), and every use its position in the user's
+//     defs, so an edge is removed in constant time by moving the last use into its place
+struct Node { opcode: OpCode, defs: Vec<NodeId>, slots: Vec<u32> }
+pub struct Arena { nodes: Vec<Option<Node>>, types: Vec<Type>, uses: Vec<Vec<(NodeId, u32)>>, free: Vec<NodeId>, gvn: HashMap<GvnKey, NodeId> }
 
-pub struct DefEdge(Rc<RefCell<Node>>); pub struct UseEdge(Weak<RefCell<Node>>);
-impl Display for DefEdge {
+impl Default for Arena { fn default() -> Self { Self::new() } }
+impl Arena {
+    pub fn new() -> Self { Self { nodes: vec![None], types: vec![Type::Bot], uses: vec![Vec::new()], free: Vec::new(), gvn: HashMap::new() } }
+
+    // NB: nodes are created untyped (⊥) until peepholed, except for constants, whose type is their value
+    pub fn new_node(&mut self, op: OpCode, defs: &[NodeId]) -> NodeId { self.alloc(op, Type::Bot, defs) }
+    pub fn new_constant(&mut self, typ: Type, start: NodeId) -> NodeId { self.alloc(OpCode::Con, typ, &[start]) }
+
+    fn alloc(&mut self, op: OpCode, typ: Type, defs: &[NodeId]) -> NodeId {
+        let node = Node { opcode: op, defs: Vec::with_capacity(defs.len()), slots: Vec::with_capacity(defs.len()) };
+        let n = match self.free.pop() {
+            Some(n) => { (self.nodes[n.index()], self.types[n.index()]) = (Some(node), typ); n },
+            None => {
+                let id = u32::try_from(self.nodes.len()).ok().and_then(NonZeroU32::new).expect("fewer than 2^32 nodes");
+                self.nodes.push(Some(node));
+                self.types.push(typ);
+                self.uses.push(Vec::new());
+                NodeId(id)
+            },
+        };
+        for &def in defs { self.add_def(n, def); }
+        n
+    }
+
+    fn node(&self, n: NodeId) -> &Node { self.nodes[n.index()].as_ref().expect("node is alive") }
+    fn node_mut(&mut self, n: NodeId) -> &mut Node { self.nodes[n.index()].as_mut().expect("node is alive") }
+
+    pub fn op(&self, n: NodeId) -> OpCode { self.node(n).opcode }
+    pub fn typ(&self, n: NodeId) -> &Type { &self.types[n.index()] }
+    pub fn set_typ(&mut self, n: NodeId, typ: Type) -> () { self.types[n.index()] = typ }
+    pub fn defs(&self, n: NodeId) -> &[NodeId] { &self.node(n).defs }
+    pub fn def(&self, n: NodeId, i: usize) -> NodeId { self.node(n).defs[i] }
+    pub fn users(&self, n: NodeId) -> impl Iterator<Item = NodeId> + '_ { self.uses[n.index()].iter().map(|&(u, _)| u) }
+    pub fn is_unused(&self, n: NodeId) -> bool { self.uses[n.index()].is_empty() }
+    pub fn is_alive(&self, n: NodeId) -> bool { self.nodes.get(n.index()).is_some_and(Option::is_some) }
+
+    // every live node, by id
+    pub fn ids(&self) -> impl Iterator<Item = NodeId> + '_ {
+        self.nodes.iter().enumerate().filter(|(_, n)| n.is_some()).map(|(i, _)| NodeId(NonZeroU32::new(i as u32).unwrap()))
+    }
+    pub fn len(&self) -> usize { self.nodes.len() - 1 - self.free.len() }
+    pub fn is_empty(&self) -> bool { self.len() == 0 }
+
+    pub fn add_def(&mut self, n: NodeId, def: NodeId) -> () {
+        let (i, slot) = (self.node(n).defs.len() as u32, self.uses[def.index()].len() as u32);
+        self.uses[def.index()].push((n, i));
+        let node = self.node_mut(n);
+        node.defs.push(def);
+        node.slots.push(slot);
+    }
+
+    // NB: swaps the i'th def while keeping the old and new def's use lists in sync
+    pub fn set_def(&mut self, n: NodeId, i: usize, def: NodeId) -> () {
+        self.unlink(n, i);
+        let slot = self.uses[def.index()].len() as u32;
+        self.uses[def.index()].push((n, i as u32));
+        let node = self.node_mut(n);
+        (node.defs[i], node.slots[i]) = (def, slot);
+    }
+
+    // NB: the defs after i move down, so their uses are renumbered
+    pub fn remove_def(&mut self, n: NodeId, i: usize) -> NodeId {
+        self.unlink(n, i);
+        let node = self.node_mut(n);
+        let def = node.defs.remove(i);
+        node.slots.remove(i);
+        for j in i..self.node(n).defs.len() {
+            let (d, slot) = (self.node(n).defs[j], self.node(n).slots[j]);
+            self.uses[d.index()][slot as usize].1 = j as u32;
+        }
+        def
+    }
+
+    pub fn pop_def(&mut self, n: NodeId) -> Option<NodeId> {
+        let i = self.node(n).defs.len().checked_sub(1)?;
+        self.unlink(n, i);
+        let node = self.node_mut(n);
+        node.slots.pop();
+        node.defs.pop()
+    }
+
+    // removes the i'th def's use of n
+    fn unlink(&mut self, n: NodeId, i: usize) -> () {
+        let (def, slot) = (self.node(n).defs[i], self.node(n).slots[i] as usize);
+        let uses = &mut self.uses[def.index()];
+        uses.swap_remove(slot);
+        if let Some(&(moved, j)) = uses.get(slot) { self.node_mut(moved).slots[j as usize] = slot as u32; }
+    }
+
+    // NB: redirects every use of old to new, after which old is unused
+    pub fn subsume(&mut self, old: NodeId, new: NodeId) -> () {
+        if old == new { return }
+        while let Some(&(user, i)) = self.uses[old.index()].last() { self.set_def(user, i as usize, new); }
+    }
+
+    // NB: frees an unused node, unlinking it from its defs. its id goes to the free list, and names the next node created
+    pub fn kill(&mut self, n: NodeId) -> () {
+        assert!(self.is_unused(n), "{} is killed while in use", self.at(n));
+        while self.pop_def(n).is_some() {}
+        (self.nodes[n.index()], self.types[n.index()]) = (None, Type::Bot);
+        self.free.push(n);
+    }
+
+    // NB: frees every node that can't be reached through defs from the roots, returning how many were freed.
+    //     the dead are unlinked from their defs before any is freed, since they can use each other (in cycles)
+    pub fn sweep(&mut self, roots: impl IntoIterator<Item = NodeId>) -> usize {
+        let (mut live, mut stack) = (vec![false; self.nodes.len()], roots.into_iter().collect::<Vec<_>>());
+        while let Some(n) = stack.pop() {
+            if mem::replace(&mut live[n.index()], true) { continue }
+            stack.extend(self.defs(n));
+        }
+        let dead = self.ids().filter(|n| !live[n.index()]).collect::<Vec<_>>();
+        for &n in &dead { while self.pop_def(n).is_some() {} }
+        for &n in &dead { self.kill(n); }
+        dead.len()
+    }
+
+    fn is_cfg(&self, n: NodeId) -> bool { match self.op(n) {
+        OpCode::Proj(_) => matches!(self.typ(n), Type::Ctrl | Type::XCtrl), // NB: projections of data (arg) are not control
+        OpCode::Start | OpCode::Stop | OpCode::Ret | OpCode::If | OpCode::Never | OpCode::Region | OpCode::Loop | OpCode::Fun(_) | OpCode::Call(_) | OpCode::CallEnd => true,
+        _ => false
+    }}
+
+    // NB: division and modulus trap on a zero divisor (or INT_MIN over -1), which happens whether or not their value
+    //     is used, so one that may trap under live control is kept alive (and in place) by its control.
+    //     one whose operands are proven not to trap is pure
+    fn traps(&self, n: NodeId) -> bool {
+        if !matches!(self.op(n), OpCode::Div | OpCode::Mod) { return false }
+        let (x, y) = (self.typ(self.def(n, 0)).bounds(), self.typ(self.def(n, 1)).bounds());
+        let Some(((lo, _), (a, b))) = x.zip(y) else { return true };
+        (a <= 0 && 0 <= b) || (lo == i32::MIN && a <= -1 && -1 <= b)
+    }
+    fn is_effect(&self, n: NodeId) -> bool {
+        self.traps(n) && !matches!(self.typ(*self.defs(n).last().unwrap()), Type::XCtrl | Type::Top)
+    }
+    fn effects(&self) -> Vec<NodeId> { self.ids().filter(|n| self.is_effect(*n)).collect() }
+
+    pub fn unique_label(&self, n: NodeId) -> String { format!("{}{}", self.at(n), n.index()) }
+
+    // a node along with its arena, for printing
+    pub fn at(&self, n: NodeId) -> NodeRef<'_> { NodeRef { arena: self, id: n } }
+}
+
+pub struct NodeRef<'a> { arena: &'a Arena, id: NodeId }
+impl Display for NodeRef<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.borrow().opcode {
+        match self.arena.op(self.id) {
             OpCode::Start => write!(f, "Start"),
+            OpCode::Stop => write!(f, "Stop"),
             OpCode::Ret => write!(f, "Ret"),
-            OpCode::Con => write!(f, "Con_{}", self.borrow().typ),
+            OpCode::Con => write!(f, "Con_{}", self.arena.typ(self.id)),
             OpCode::Add => write!(f, "Add"),
             OpCode::Sub => write!(f, "Sub"),
             OpCode::Mul => write!(f, "Mul"),
             OpCode::Div => write!(f, "Div"),
+            OpCode::Mod => write!(f, "Mod"),
+            OpCode::Neg => write!(f, "Neg"),
+            OpCode::Not => write!(f, "Not"),
+            OpCode::Compl => write!(f, "Compl"),
+            OpCode::Eq => write!(f, "Eq"),
+            OpCode::Ne => write!(f, "Ne"),
+            OpCode::Lt => write!(f, "Lt"),
+            OpCode::Le => write!(f, "Le"),
             OpCode::Scope => write!(f, "Scope"),
+            OpCode::If => write!(f, "If"),
+            OpCode::Never => write!(f, "Never"),
+            OpCode::Proj(i) => write!(f, "Proj_{i}"),
+            OpCode::Region => write!(f, "Region"),
+            OpCode::Loop => write!(f, "Loop"),
+            OpCode::Phi => write!(f, "Phi"),
+            OpCode::Fun(i) => write!(f, "Fun_{i}"),
+            OpCode::Parm(i) => write!(f, "Parm_{i}"),
+            OpCode::Call(i) => write!(f, "Call_{i}"),
+            OpCode::CallEnd => write!(f, "CallEnd"),
         }
     }
 }
 
-impl Debug for DefEdge {
+impl Debug for NodeRef<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self.borrow().opcode {
+        match self.arena.op(self.id) {
             OpCode::Start => write!(f, "Start"),
+            OpCode::Stop => write!(f, "Stop"),
             OpCode::Ret => write!(f, "Ret"),
-            OpCode::Con => write!(f, "{}", self.borrow().typ),
+            OpCode::Con => write!(f, "{}", self.arena.typ(self.id)),
             OpCode::Add => write!(f, "+"),
             OpCode::Sub => write!(f, "-"),
             OpCode::Mul => write!(f, "*"),
             OpCode::Div => write!(f, "/"),
+            OpCode::Mod => write!(f, "%"),
+            OpCode::Neg => write!(f, "-"),
+            OpCode::Not => write!(f, "!"),
+            OpCode::Compl => write!(f, "~"),
+            OpCode::Eq => write!(f, "=="),
+            OpCode::Ne => write!(f, "!="),
+            OpCode::Lt => write!(f, "<"),
+            OpCode::Le => write!(f, "<="),
             OpCode::Scope => write!(f, "nv"),
+            OpCode::If => write!(f, "If"),
+            OpCode::Never => write!(f, "Never"),
+            OpCode::Proj(i) => write!(f, "#{i}"),
+            OpCode::Region => write!(f, "Region"),
+            OpCode::Loop => write!(f, "Loop"),
+            OpCode::Phi => write!(f, "Phi"),
+            OpCode::Fun(i) => write!(f, "Fun{i}"),
+            OpCode::Parm(i) => write!(f, "Parm#{i}"),
+            OpCode::Call(i) => write!(f, "Call{i}"),
+            OpCode::CallEnd => write!(f, "CallEnd"),
         }
     }
 }
-
-impl DefEdge {
-    pub fn new_constant(op: OpCode, typ: Type) -> Self { Self(Rc::new(RefCell::new(Node { id: generate_nodeid(), opcode: op, typ, defs: VecDeque::new(), uses: VecDeque::new()}))) }
-    pub fn new(op: OpCode) -> Self { Self(Rc::new(RefCell::new(Node { id: generate_nodeid(), opcode: op, typ: Type::Bot, defs: VecDeque::new(), uses: VecDeque::new() }))) }
-    fn from_upgraded(strong: Rc<RefCell<Node>>) -> Self { Self(strong) }
-
-    pub fn add_def(&self, def: &Self) -> () {
-        self.borrow_mut().defs.push_back(def.clone());
-        def.borrow_mut().uses.push_back(UseEdge::new(self));
-    }
-
-    fn is_cfg(&self) -> bool { match self.borrow().opcode {
-        OpCode::Start | OpCode::Ret => true,
-        _ => false
-    }}
-
-    pub fn unique_label(&self) -> String { format!("{}{}", self.to_string(), self.borrow().id) }
-}
-
-impl UseEdge { fn new(e: &DefEdge) -> Self { Self(Rc::downgrade(&e.0)) }}
-impl Deref for DefEdge { type Target = Rc<RefCell<Node>>; fn deref(&self) -> &Self::Target { &self.0 }}
-impl Deref for UseEdge { type Target = Weak<RefCell<Node>>; fn deref(&self) -> &Self::Target { &self.0 }}
-impl Clone for DefEdge { fn clone(&self) -> Self { Self(self.0.clone()) }}
-impl Clone for UseEdge { fn clone(&self) -> Self { Self(self.0.clone()) }}
-impl PartialEq for UseEdge { fn eq(&self, other: &Self) -> bool { Weak::ptr_eq(&self.0, &other.0) }}
-impl Drop for DefEdge { fn drop(&mut self) {
-    if Rc::strong_count(&self.0) == 1 {
-        while let Some(mut def) = self.borrow_mut().defs.pop_back() { def.del_use(&UseEdge::new(&self)).unwrap(); }
-    }
-}}
-impl DefEdge {
-    pub fn del_use(&mut self, u_target: &UseEdge) -> Result<(), NodeError> {
-        let i = &self.borrow().uses.iter().position(|u| u == u_target).ok_or(NodeError::UseNotFound)?;
-        let uses = &mut self.borrow_mut().uses;
-        let _ = uses.make_contiguous();
-        let (head, _tail) = uses.as_mut_slices();
-        head.swap(*i, head.len()-1);
-        uses.pop_back();
-        Ok(())
-    }
-}
\ No newline at end of file
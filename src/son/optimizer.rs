@@ -1,5 +1,6 @@
-use std::{fmt::Display, mem};
-use crate::son::{DefEdge, OpCode};
+use std::{collections::BTreeSet, fmt::Display};
+use thiserror::Error;
+use crate::son::{parser::ParseResult, Arena, NodeId, OpCode};
 
 // types form a symmetric complete bounded (ranked) lattice
 // see: https://en.wikipedia.org/wiki/Lattice_(order)
@@ -9,87 +10,502 @@ use crate::son::{DefEdge, OpCode};
 //     bot => not constants.
 
 //     peepholes are pessimistic, assuming worst case scenario
-//     by starting at the bottom of the latice until proven better
+//     by starting at the bottom of the latice until proven better.
+//     sccp is optimistic, starting at the top until proven worse (see sccp)
 //     **for now, the only constants are integers.
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum Type { Bot, Top, Simple, Int(i128) } //, Tup(Vec<Box<Self>>) }
+//     control is a two point lattice: ctrl (live) sits below ~ctrl (dead)
+//     integers are ranges [lo, hi] of 32-bit values, ordered componentwise: meet takes the lower lo and the
+//     higher hi (the union's hull). [c, c] is the constant c (always written Int(c)), [MIN, MAX] is any int,
+//     and bool is [0, 1]. ranges with lo > hi are their dual, sitting above the constants, with [MAX, MIN] on top
+//     tuples are the types of multi-valued nodes (start), whose fields are selected by projections
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Type { Bot, Top, Ctrl, XCtrl, Int(i32), Range(i32, i32), Tup(Vec<Self>) }
 impl Display for Type {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { match self {
         Self::Bot => write!(f, "⊥"),
         Self::Top => write!(f, "⊤"),
-        Self::Simple => write!(f, "simple"),
+        Self::Ctrl => write!(f, "ctrl"),
+        Self::XCtrl => write!(f, "~ctrl"),
         Self::Int(val) => write!(f, "{}", val),
+        &Self::INT => write!(f, "int"),
+        &Self::BOOL => write!(f, "bool"),
+        Self::Range(lo, hi) => write!(f, "[{lo}, {hi}]"),
+        Self::Tup(ts) => write!(f, "[{}]", ts.iter().map(|t| t.to_string()).collect::<Vec<_>>().join(", ")),
     }}
 }
 impl Type {
+    pub const INT: Self = Self::Range(i32::MIN, i32::MAX);
+    pub const BOOL: Self = Self::Range(0, 1);
+
+    // NB: the only constructor of ranges, keeping constants canonical
+    pub fn range(lo: i32, hi: i32) -> Self { if lo == hi { Self::Int(lo) } else { Self::Range(lo, hi) } }
+
+    // the bounds of a (low) integer range, which are the only ones values are computed over
+    pub fn bounds(&self) -> Option<(i32, i32)> { match self {
+        Self::Int(c) => Some((*c, *c)),
+        Self::Range(lo, hi) if lo <= hi => Some((*lo, *hi)),
+        _ => None,
+    }}
+
     pub fn is_constant(&self) -> bool { match self {
         Self::Bot => false,
         Self::Top => true,
-        Self::Simple => todo!(),
+        Self::Ctrl => false,
+        Self::XCtrl => true,
         Self::Int(_) => true,
-        // Type::Tup(type_and_vals) => todo!(),
+        Self::Range(..) => false,
+        Self::Tup(_) => false, // NB: tuples are never folded, their projections are
+    }}
+
+    // greatest lower bound: moves down the lattice towards ⊥
+    pub fn meet(&self, other: &Self) -> Self { match (self, other) {
+        (Self::Top, t) | (t, Self::Top) => t.clone(),
+        (Self::XCtrl, Self::XCtrl) => Self::XCtrl,
+        (Self::Ctrl | Self::XCtrl, Self::Ctrl | Self::XCtrl) => Self::Ctrl,
+        (Self::Int(x) | Self::Range(x, _), Self::Int(y) | Self::Range(y, _)) => {
+            let (Self::Int(a) | Self::Range(_, a), Self::Int(b) | Self::Range(_, b)) = (self, other) else { unreachable!() };
+            Self::range(*x.min(y), *a.max(b))
+        },
+        (Self::Tup(xs), Self::Tup(ys)) if xs.len() == ys.len() => Self::Tup(xs.iter().zip(ys).map(|(x, y)| x.meet(y)).collect()),
+        _ => Self::Bot,
+    }}
+
+    // least upper bound: moves up the lattice towards ⊤
+    pub fn join(&self, other: &Self) -> Self { self.dual().meet(&other.dual()).dual() }
+
+    // the mirror image across the constants, which are their own duals
+    pub fn dual(&self) -> Self { match self {
+        Self::Bot => Self::Top,
+        Self::Top => Self::Bot,
+        Self::Ctrl => Self::XCtrl,
+        Self::XCtrl => Self::Ctrl,
+        Self::Int(c) => Self::Int(*c),
+        Self::Range(lo, hi) => Self::Range(*hi, *lo),
+        Self::Tup(ts) => Self::Tup(ts.iter().map(|t| t.dual()).collect()),
     }}
 }
 
-impl DefEdge {
-    pub fn peephole(self, start_node: &DefEdge ) -> DefEdge {
-        self.borrow_mut().typ = self.eval();
-        let peepholed = match (self.borrow().opcode, self.borrow().typ.is_constant()) {
-            (OpCode::Con, true) | (_, false) => None,
+// NB: peephole runs once per node as it's parsed, when its users don't exist yet (and loop phis are missing
+//     their back edge), so improving a node never revisits them. iterate re-runs peephole over the whole graph,
+//     requeueing the uses of every node that's replaced or whose type changes, and the defs left behind by a
+//     replacement, until nothing changes. the worklist is ordered by node id, so the result is reproducible
+#[derive(Error, Debug)] pub enum OptError { #[error("peephole did not settle within {budget} steps")] Budget { budget: usize } }
+
+pub fn iterate(graph: &mut ParseResult, budget: usize) -> Result<usize, OptError> {
+    let (arena, start) = (&mut graph.arena, graph.start);
+    let mut work = nodes(arena);
+    let (mut steps, mut changes) = (0, 0);
+    while let Some(old) = work.pop_first() {
+        if steps == budget { return Err(OptError::Budget { budget }) }
+        steps += 1;
+        // NB: ids of freed nodes are reused, so a queued id may since name another node, which is as good to visit.
+        //     unused nodes are dead, and freeing them leaves their defs to be visited, since they may be dead next
+        if !arena.is_alive(old) { continue }
+        if is_dead(arena, old) {
+            work.extend(arena.defs(old).iter().filter(|d| arena.op(**d) != OpCode::Scope));
+            arena.kill(old);
+            continue
+        }
+
+        let typ = arena.typ(old).clone();
+        let new = arena.peephole(old, start);
+        let requeue = if new != old {
+            arena.subsume(old, new);
+            let requeue = arena.defs(old).iter().copied().chain([new]).chain(arena.users(new)).collect::<Vec<_>>();
+            arena.kill(old);
+            requeue
+        } else if *arena.typ(new) != typ { arena.users(new).collect() } else { continue };
+
+        changes += 1;
+        for n in requeue {
+            if n == old || arena.op(n) == OpCode::Scope { continue }
+            work.insert(n);
+        }
+    }
+    Ok(changes)
+}
+
+// NB: sparse conditional constant propagation. peepholes are pessimistic, so a value flowing around a loop
+//     is never better than what the loop's phi assumed before its back edge was parsed. sccp is optimistic:
+//     every node starts at ⊤ (not computed, and for control, not reached), and only moves down the lattice
+//     when its inputs prove it must, so a value is assumed constant (and a path dead) until shown otherwise.
+//     the fixed point is then applied to the graph: data proven to be an integer is replaced by a constant, the live
+//     arm of an if with a dead arm continues the if's control, and dead inputs of regions and funs (with their phis'
+//     and parms' inputs) are deleted. the dead control left unused is freed, and the rest is left to eliminate
+pub fn sccp(graph: &mut ParseResult, budget: usize) -> Result<usize, OptError> {
+    let (arena, start) = (&mut graph.arena, graph.start);
+    let all = nodes(arena);
+    for &n in &all {
+        if !matches!(arena.op(n), OpCode::Con | OpCode::Ret) { arena.set_typ(n, Type::Top); }
+    }
+
+    let (mut work, mut steps) = (all.clone(), 0);
+    while let Some(n) = work.pop_first() {
+        if steps == budget { return Err(OptError::Budget { budget }) }
+        steps += 1;
+
+        let (old, mut new) = (arena.typ(n).clone(), arena.eval(n));
+        if new == old { continue }
+        // NB: widening. a range around a loop could otherwise grow by one per iteration
+        let looped = arena.op(n) == OpCode::Phi && arena.op(arena.def(n, 0)) == OpCode::Loop;
+        if looped && old.bounds().is_some() && new.bounds().is_some() { new = new.meet(&Type::INT); }
+        arena.set_typ(n, new);
+
+        // NB: phis depend on the control of their region's inputs, not only on their own
+        let regions = arena.users(n).filter(|u| matches!(arena.op(*u), OpCode::Region | OpCode::Loop));
+        let requeue = arena.users(n).chain(regions.flat_map(|r| arena.users(r))).collect::<Vec<_>>();
+        work.extend(requeue.into_iter().filter(|u| arena.op(*u) != OpCode::Scope));
+    }
+
+    let mut changes = 0;
+    for &n in &all {
+        let (op, typ) = (arena.op(n), arena.typ(n).clone());
+        if op == OpCode::Con || !matches!(typ, Type::Int(_)) || arena.is_cfg(n) || arena.is_unused(n) { continue }
+        let con = arena.constant(typ, start);
+        arena.subsume(n, con);
+        changes += 1;
+    }
+    for &n in &all {
+        let (op, typ) = (arena.op(n), arena.typ(n).clone());
+        match (op, typ) {
+            (OpCode::If, Type::Tup(arms)) if arms.contains(&Type::XCtrl) && arms.contains(&Type::Ctrl) => {
+                let ctrl = arena.def(n, 0);
+                let live = arena.users(n).find(|p| *arena.typ(*p) == Type::Ctrl);
+                let Some(proj) = live else { continue };
+                arena.subsume(proj, ctrl);
+                changes += 1;
+            },
+            (OpCode::Region | OpCode::Loop | OpCode::Fun(_), Type::Ctrl) => {
+                let dead = arena.defs(n).iter().enumerate().filter(|(_, d)| *arena.typ(**d) == Type::XCtrl).map(|(i, _)| i).collect::<Vec<_>>();
+                if dead.is_empty() { continue }
+                let phis = arena.users(n).filter(|u| matches!(arena.op(*u), OpCode::Phi | OpCode::Parm(_))).collect::<Vec<_>>();
+                for &i in dead.iter().rev() {
+                    arena.remove_def(n, i);
+                    for &phi in &phis { arena.remove_def(phi, i + 1); }
+                }
+                if arena.defs(n).len() == 1 && !matches!(op, OpCode::Fun(_)) {
+                    for &phi in &phis { let value = arena.def(phi, 1); arena.subsume(phi, value); }
+                    let ctrl = arena.def(n, 0);
+                    arena.subsume(n, ctrl);
+                }
+                changes += 1;
+            },
+            _ => (),
+        }
+    }
+
+    // NB: the nodes replaced above are freed, along with the defs only they used
+    let mut dead = all;
+    while let Some(n) = dead.pop_last() {
+        if !arena.is_alive(n) || !is_dead(arena, n) { continue }
+        dead.extend(arena.defs(n).iter().filter(|d| arena.op(**d) != OpCode::Scope));
+        arena.kill(n);
+    }
+    Ok(changes)
+}
+
+// NB: the live graph is everything reachable from stop through defs, along with the fun and parms of every
+//     function (its signature, which schedule starts from) and the divisions that may trap. the parser's scope
+//     is closed, so its node is kept but holds nothing. returns under dead control are unlinked from stop and
+//     from the call ends they flow into, and infinite loops are anchored to stop (see anchor). everything else
+//     is freed, so live nodes are left with only live uses
+pub fn eliminate(graph: &mut ParseResult) -> usize {
+    let arena = &mut graph.arena;
+    graph.scope.close(arena);
+    for f in graph.funs.iter_mut() {
+        let (dead, live) = f.rets.iter().partition::<Vec<NodeId>, _>(|r| *arena.typ(arena.def(**r, 0)) == Type::XCtrl);
+        for ret in dead {
+            loop {
+                let Some(u) = arena.users(ret).next() else { break };
+                let i = arena.defs(u).iter().position(|d| *d == ret).unwrap();
+                arena.remove_def(u, i);
+            }
+        }
+        f.rets = live;
+    }
+    anchor(graph);
+
+    let arena = &mut graph.arena;
+    let sigs = graph.funs.iter().flat_map(|f| [f.fun].into_iter().chain(f.parms.iter().copied()));
+    let roots = [graph.stop, graph.scope.lookup].into_iter().chain(sigs).chain(arena.effects()).collect::<Vec<_>>();
+    arena.sweep(roots)
+}
+
+// NB: an infinite loop reaches no return, so nothing reachable from stop uses it. each is anchored by a never
+//     out of its head, whose exit (which is never taken) falls into stop, and whose other arm continues the loop
+fn anchor(graph: &mut ParseResult) -> () {
+    let (arena, start) = (&mut graph.arena, graph.start);
+    let loops = arena.ids().filter(|&n| arena.op(n) == OpCode::Loop && *arena.typ(arena.def(n, 0)) == Type::Ctrl).collect::<Vec<_>>();
+
+    let (mut reached, mut stack) = (BTreeSet::new(), vec![graph.stop]);
+    for head in loops {
+        while let Some(n) = stack.pop() { if reached.insert(n) { stack.extend(arena.defs(n)); } }
+        if reached.contains(&head) { continue }
+
+        let never = arena.new_node(OpCode::Never, &[head]);
+        let never = arena.peephole(never, start);
+        let (exit, stay) = (arena.new_node(OpCode::Proj(0), &[never]), arena.new_node(OpCode::Proj(1), &[never]));
+        let (exit, stay) = (arena.peephole(exit, start), arena.peephole(stay, start));
+
+        // NB: the loop's body (and its back edge, when the body is empty) now hangs off the arm that stays in it
+        let body = arena.users(head).filter(|&u| u != never && arena.op(u) != OpCode::Phi).collect::<Vec<_>>();
+        for u in body {
+            while let Some(i) = arena.defs(u).iter().position(|d| *d == head) { arena.set_def(u, i, stay); }
+        }
+        arena.add_def(graph.stop, exit);
+        stack.push(exit);
+    }
+}
+
+// NB: an unused node is dead, unless the graph holds it: start, stop, the entries, parms and returns of functions,
+//     and the divisions that may trap
+fn is_dead(arena: &Arena, n: NodeId) -> bool {
+    arena.is_unused(n) && !arena.is_effect(n) && !matches!(arena.op(n), OpCode::Start | OpCode::Stop | OpCode::Ret | OpCode::Fun(_) | OpCode::Parm(_) | OpCode::Scope)
+}
+
+// every node of the graph, ordered by id. NB: the scope isn't a node
+fn nodes(arena: &Arena) -> BTreeSet<NodeId> { arena.ids().filter(|n| arena.op(*n) != OpCode::Scope).collect() }
+
+// NB: the table is keyed by the ids of a node's defs, so it lives in the arena
+pub(super) type GvnKey = (OpCode, Type, Vec<NodeId>);
+
+// NB: -O0 keeps the graph the frontend builds. nodes are still typed (projections need their parent's type
+//     to tell control from data), but never folded, idealized or numbered
+#[derive(Clone, Copy, Debug, Default, PartialEq)] pub enum OptLevel { O0, #[default] O1 }
+
+impl Arena {
+    pub fn peephole_at(&mut self, n: NodeId, start_node: NodeId, opt: OptLevel) -> NodeId {
+        match opt {
+            OptLevel::O0 => { let typ = self.eval(n); self.set_typ(n, typ); n },
+            OptLevel::O1 => self.peephole(n, start_node),
+        }
+    }
+
+    // NB: only data proven to be an integer is folded. control (even dead control) keeps its place in the cfg,
+    //     and tuples are never folded (their projections are)
+    pub fn peephole(&mut self, n: NodeId, start_node: NodeId) -> NodeId {
+        let typ = self.eval(n);
+        self.set_typ(n, typ);
+        let folds = matches!(self.typ(n), Type::Int(_)) && !self.is_cfg(n);
+        let peepholed = match (self.op(n), folds) {
+            (OpCode::Phi, false) => self.redundant_phi(n),
+            (OpCode::Con, true) => None,
+            (_, false) => self.idealize(n, start_node).map(|ideal| self.peephole(ideal, start_node)),
             (_, true) => {
-                let con = DefEdge::new_constant( OpCode::Con, self.borrow().typ);
-                let _ = con.add_def(start_node);
-                println!("constant folded with node: {:?}", con);
-                Some(con)
+                let con = self.new_constant(self.typ(n).clone(), start_node);
+                Some(self.gvn(con))
             },
         };
- 
-        // NB: explicit drop over implicit for asserting invariant: this node (and it's edges) SHOULD be droppable.
-        match peepholed { Some(peeped) => { mem::drop(self); peeped } None => self }
+
+        // NB: a replaced node that's unused (one just built) is freed. one in use is left to the caller to subsume
+        match peepholed {
+            Some(peeped) => { if self.is_unused(n) { self.kill(n); } peeped },
+            None => self.gvn(n),
+        }
+    }
+
+    // NB: global value numbering by hash-consing: a pure node equal to one already built (same opcode,
+    //     type and inputs) is replaced by it. entries are checked against their node's current key on lookup,
+    //     so nodes whose inputs were since edited, or which were freed (and their ids reused), are missed
+    fn gvn(&mut self, n: NodeId) -> NodeId {
+        let Some(key) = self.gvn_key(n) else { return n };
+        match self.gvn.get(&key).copied() {
+            Some(existing) if existing != n && self.is_alive(existing) && self.gvn_key(existing).as_ref() == Some(&key) => {
+                if self.is_unused(n) { self.kill(n); }
+                existing
+            },
+            _ => { self.gvn.insert(key, n); n },
+        }
+    }
+
+    // NB: only pure data nodes are numbered, since control and phis are identified by their position in the cfg.
+    //     the operands of commutative nodes are sorted, so x+y and y+x share a number
+    fn gvn_key(&self, n: NodeId) -> Option<GvnKey> {
+        let mut ids = self.defs(n).to_vec();
+        match self.op(n) {
+            OpCode::Add | OpCode::Mul | OpCode::Eq | OpCode::Ne => ids.sort(),
+            OpCode::Con | OpCode::Sub | OpCode::Div | OpCode::Mod | OpCode::Neg | OpCode::Not | OpCode::Compl | OpCode::Lt | OpCode::Le => (),
+            _ => return None,
+        }
+        Some((self.op(n), self.typ(n).clone(), ids))
     }
 
     // see: https://en.wikipedia.org/wiki/Partial_evaluation
-    fn eval(&self) -> Type { // NB: a type is modelled as a set of values/operations
-        match self.borrow().opcode {
-            OpCode::Start => Type::Bot, OpCode::Ret => Type::Bot,
-            OpCode::Con => self.borrow().typ.clone(), // con's already have static type (dynamic value)
-            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div => {
-                let (x_type, y_type) = (self.borrow().defs[0].borrow().typ, self.borrow().defs[1].borrow().typ);
-                let evald_type = match (x_type, y_type) {
-                    (Type::Int(x), Type::Int(y)) => match self.borrow().opcode {
-                        // partial evaluation, TODO: semantics are inherited from rust
-                        OpCode::Add => Type::Int(x+y), 
-                        OpCode::Sub => Type::Int(x-y),
-                        OpCode::Mul => Type::Int(x*y),
-                        OpCode::Div => Type::Int(x/y),
-                        _ => panic!()
+    fn eval(&self, n: NodeId) -> Type { // NB: a type is modelled as a set of values/operations
+        // NB: ⊤ inputs (only seen by sccp) are values not computed yet, so neither is the result
+        let (op, defs) = (self.op(n), self.defs(n));
+        let data = matches!(op, OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Mod | OpCode::Neg | OpCode::Not | OpCode::Compl | OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le);
+        if data && defs.iter().any(|d| *self.typ(*d) == Type::Top) { return Type::Top }
+        match op {
+            // NB: start produces the program's control and its (unknown) argument
+            OpCode::Start => Type::Tup(vec![Type::Ctrl, Type::INT]), OpCode::Stop | OpCode::Ret => Type::Bot,
+            OpCode::Con => self.typ(n).clone(), // con's already have static type (dynamic value)
+            OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Div | OpCode::Mod => {
+                let (x_type, y_type) = (self.typ(defs[0]).clone(), self.typ(defs[1]).clone());
+                match (x_type, y_type) {
+                    // partial evaluation with C0 semantics: 32-bit two's complement arithmetic wraps around,
+                    // and division (and modulus) by zero or of INT_MIN by -1 traps at runtime, so they aren't folded
+                    (Type::Int(x), Type::Int(y)) => match op {
+                        OpCode::Add => Type::Int(x.wrapping_add(y)),
+                        OpCode::Sub => Type::Int(x.wrapping_sub(y)),
+                        OpCode::Mul => Type::Int(x.wrapping_mul(y)),
+                        OpCode::Div => x.checked_div(y).map_or(Type::INT, Type::Int),
+                        OpCode::Mod => x.checked_rem(y).map_or(Type::INT, Type::Int),
+                        _ => unreachable!()
                     }
-                    _ => Type::Bot,
+                    // NB: ranges are computed from their bounds' extremes, unless they might wrap around
+                    (x, y) => match (op, x.bounds(), y.bounds()) {
+                        (OpCode::Add, Some((a, b)), Some((c, d))) => a.checked_add(c).zip(b.checked_add(d)).map_or(Type::INT, |(lo, hi)| Type::range(lo, hi)),
+                        (OpCode::Sub, Some((a, b)), Some((c, d))) => a.checked_sub(d).zip(b.checked_sub(c)).map_or(Type::INT, |(lo, hi)| Type::range(lo, hi)),
+                        (OpCode::Mul, Some((a, b)), Some((c, d))) => {
+                            let corners = [a.checked_mul(c), a.checked_mul(d), b.checked_mul(c), b.checked_mul(d)];
+                            corners.iter().copied().collect::<Option<Vec<_>>>()
+                                .map_or(Type::INT, |cs| Type::range(*cs.iter().min().unwrap(), *cs.iter().max().unwrap()))
+                        },
+                        _ => Type::INT,
+                    }
+                }
+            },
+            OpCode::Neg | OpCode::Not | OpCode::Compl => {
+                let x_type = self.typ(defs[0]);
+                match (op, x_type.bounds()) {
+                    (OpCode::Neg, Some((lo, hi))) if lo != i32::MIN => Type::range(-hi, -lo),
+                    (OpCode::Neg, Some((lo, lo_))) if lo == lo_ => Type::Int(lo.wrapping_neg()),
+                    (OpCode::Compl, Some((lo, hi))) => Type::range(!hi, !lo),
+                    (OpCode::Not, Some((0, 0))) => Type::Int(1),
+                    (OpCode::Not, Some((lo, hi))) if lo > 0 || hi < 0 => Type::Int(0),
+                    (OpCode::Not, _) => Type::BOOL,
+                    _ => Type::INT,
+                }
+            },
+            // NB: comparisons are decided when the ranges don't overlap (or are the same constant)
+            OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le => {
+                let (x_type, y_type) = (self.typ(defs[0]), self.typ(defs[1]));
+                let Some(((a, b), (c, d))) = x_type.bounds().zip(y_type.bounds()) else { return Type::BOOL };
+                let decided = match op {
+                    OpCode::Eq | OpCode::Ne => if a == b && b == c && c == d { Some(true) } else if b < c || d < a { Some(false) } else { None },
+                    OpCode::Lt => if b < c { Some(true) } else if a >= d { Some(false) } else { None },
+                    OpCode::Le => if b <= c { Some(true) } else if a > d { Some(false) } else { None },
+                    _ => unreachable!()
                 };
-
-                evald_type
+                let ne = op == OpCode::Ne;
+                decided.map_or(Type::BOOL, |eq| Type::Int((eq != ne) as i32))
+            },
+            // NB: an if splits control into its (true, false) arms. control dies with its input, and
+            //     a predicate known to be (non)zero kills the arm that can't be taken
+            OpCode::If => {
+                let (ctrl, pred) = (self.typ(defs[0]), self.typ(defs[1]));
+                match (ctrl, pred.bounds()) {
+                    (Type::XCtrl | Type::Top, _) => Type::Tup(vec![Type::XCtrl, Type::XCtrl]),
+                    (_, None) if *pred == Type::Top => Type::Tup(vec![Type::XCtrl, Type::XCtrl]),
+                    (_, Some((0, 0))) => Type::Tup(vec![Type::XCtrl, Type::Ctrl]),
+                    (_, Some((lo, hi))) if lo > 0 || hi < 0 => Type::Tup(vec![Type::Ctrl, Type::XCtrl]),
+                    _ => Type::Tup(vec![Type::Ctrl, Type::Ctrl]),
+                }
+            },
+            // NB: a never (see anchor) is an if that's never taken, though both of its arms are live to every pass
+            OpCode::Never => match self.typ(defs[0]) {
+                Type::XCtrl | Type::Top => Type::Tup(vec![Type::XCtrl, Type::XCtrl]),
+                _ => Type::Tup(vec![Type::Ctrl, Type::Ctrl]),
+            },
+            // NB: a projection selects its field of the parent's tuple
+            OpCode::Proj(i) => match self.typ(defs[0]) {
+                Type::Tup(ts) => ts[i].clone(),
+                Type::XCtrl => Type::XCtrl,
+                Type::Top => Type::Top,
+                _ => Type::Bot,
+            },
+            // NB: functions are live (and their parameters unknown) until proven otherwise
+            OpCode::Fun(_) => Type::Ctrl, OpCode::Parm(_) => Type::INT,
+            OpCode::Call(_) => match self.typ(defs[0]) { Type::XCtrl | Type::Top => Type::XCtrl, _ => Type::Ctrl },
+            // NB: the call end produces the control and the (unknown) value flowing out of the callee
+            OpCode::CallEnd => match self.typ(defs[0]) {
+                Type::XCtrl | Type::Top => Type::Tup(vec![Type::XCtrl, Type::Top]),
+                _ => Type::Tup(vec![Type::Ctrl, Type::INT]),
+            },
+            OpCode::Region | OpCode::Loop => defs.iter().fold(Type::XCtrl, |t, d| t.meet(self.typ(*d))),
+            // NB: values flowing in along dead paths don't reach the merge. (the back edge of a loop in progress is still missing)
+            OpCode::Phi => {
+                let region = self.defs(defs[0]);
+                let live = |i: usize| region.get(i).is_none_or(|c| !matches!(self.typ(*c), Type::XCtrl | Type::Top));
+                defs.iter().skip(1).enumerate().filter(|(i, _)| live(*i)).fold(Type::Top, |t, (_, d)| t.meet(self.typ(*d)))
             },
             _ => unimplemented!()
         }
     }
 
-    fn _idealize(&self) -> Self {
-        todo!()
+    // a phi is redundant when all of its inputs are the same node, or the phi itself (a loop that never
+    // updates the variable). NB: phis of loops in progress are missing the back edge, so they are skipped
+    fn redundant_phi(&self, n: NodeId) -> Option<NodeId> {
+        let (defs, region) = (self.defs(n), self.def(n, 0));
+        if defs.len() - 1 != self.defs(region).len() { return None }
+        let mut data = defs.iter().skip(1).filter(|d| **d != n);
+        let first = *data.next()?;
+        if data.all(|d| *d == first) { Some(first) } else { None }
+    }
+
+    // NB: rewrites n into a simpler (or more canonical) node, which is then peepholed itself.
+    //     x*0 needs no rule, since the range of any int times 0 is the constant 0. rules:
+    //     - identities: x+0 => x, x-0 => x, x*1 => x, x-x => 0, and comparisons of x with itself
+    //     - commutative operands are ordered with constants on the right
+    //     - additions are rotated into a left spine with the constant last, so constant chains meet and fold:
+    //       x+(y+z) => (x+y)+z, (x+c)+y => (x+y)+c, (x+c1)+c2 => x+(c1+c2) and likewise for multiplication
+    //     - x+x => x*2
+    fn idealize(&mut self, n: NodeId, start_node: NodeId) -> Option<NodeId> {
+        let op = self.op(n);
+        if !matches!(op, OpCode::Add | OpCode::Sub | OpCode::Mul | OpCode::Eq | OpCode::Ne | OpCode::Lt | OpCode::Le) { return None }
+        let (x, y) = (self.def(n, 0), self.def(n, 1));
+        let (x_con, y_con) = (self.typ(x).is_constant(), self.typ(y).is_constant());
+        let same = |a: NodeId, b: OpCode| std::mem::discriminant(&self.op(a)) == std::mem::discriminant(&b);
+        let (same_x, same_y) = (same(x, op), same(y, op));
+        let (yc, xx) = (self.typ(y).clone(), x == y);
+
+        match op {
+            OpCode::Add | OpCode::Mul | OpCode::Eq | OpCode::Ne if x_con && !y_con => Some(self.binary(op, y, x)),
+            OpCode::Add | OpCode::Sub if yc == Type::Int(0) => Some(x),
+            OpCode::Mul if yc == Type::Int(1) => Some(x),
+            OpCode::Sub | OpCode::Ne | OpCode::Lt if xx => Some(self.constant(Type::Int(0), start_node)),
+            OpCode::Eq | OpCode::Le if xx => Some(self.constant(Type::Int(1), start_node)),
+            OpCode::Add if xx => { let two = self.constant(Type::Int(2), start_node); Some(self.binary(OpCode::Mul, x, two)) },
+            OpCode::Add | OpCode::Mul if same_y => {
+                let (y0, y1) = (self.def(y, 0), self.def(y, 1));
+                let xy0 = self.binary(op, x, y0);
+                let xy0 = self.peephole(xy0, start_node);
+                Some(self.binary(op, xy0, y1))
+            },
+            OpCode::Add | OpCode::Mul if same_x && self.typ(self.def(x, 1)).is_constant() => {
+                let (x0, x1) = (self.def(x, 0), self.def(x, 1));
+                let (inner, outer) = if y_con { ((x1, y), x0) } else { ((x0, y), x1) };
+                let inner = self.binary(op, inner.0, inner.1);
+                let inner = self.peephole(inner, start_node);
+                Some(if y_con { self.binary(op, outer, inner) } else { self.binary(op, inner, outer) })
+            },
+            _ => None,
+        }
+    }
+
+    fn binary(&mut self, op: OpCode, x: NodeId, y: NodeId) -> NodeId { self.new_node(op, &[x, y]) }
+
+    fn constant(&mut self, typ: Type, start_node: NodeId) -> NodeId {
+        let con = self.new_constant(typ, start_node);
+        self.peephole(con, start_node)
     }
 }
 
 #[cfg(test)]
 mod test_optimizer {
-    use crate::son::{dumper, parser, utils::read_chars};
+    use crate::son::{dumper, optimizer::{self, OptLevel, Type}, parser::{self, ParseResult}, scheduler, utils::read_chars, Arena, NodeId, OpCode};
     use std::{assert_matches::assert_matches, path::Path};
     
-    const TEST_DIR: &str = "tests/arith";
+    const TEST_DIR: &str = "tests/c0/arith";
+    const CONTROL_DIR: &str = "tests/c0/control";
 
     #[test]
     fn add() {
         let chars = read_chars(Path::new(&format!("{TEST_DIR}/add.c")));
-        let graph = parser::parse(&chars).unwrap();
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
         let dot = dumper::dump_dot(&chars, &graph).unwrap();
         println!("{dot}");
 
@@ -101,7 +517,7 @@ mod test_optimizer {
     #[test]
     fn sub() {
         let chars = read_chars(Path::new(&format!("{TEST_DIR}/sub.c")));
-        let graph = parser::parse(&chars).unwrap();
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
 
         // assert_matches!(graph.borrow().opcode, OpCode::Ret);
         // assert_matches!(graph.borrow().defs[0].borrow().opcode, OpCode::Start);
@@ -111,7 +527,7 @@ mod test_optimizer {
     #[test]
     fn mul() {
         let chars = read_chars(Path::new(&format!("{TEST_DIR}/mul.c")));
-        let graph = parser::parse(&chars).unwrap();
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
 
         // assert_matches!(graph.borrow().opcode, OpCode::Ret);
         // assert_matches!(graph.borrow().defs[0].borrow().opcode, OpCode::Start);
@@ -121,7 +537,7 @@ mod test_optimizer {
     #[test]
     fn div() {
         let chars = read_chars(Path::new(&format!("{TEST_DIR}/div.c")));
-        let graph = parser::parse(&chars).unwrap();
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
 
         // assert_matches!(graph.borrow().opcode, OpCode::Ret);
         // assert_matches!(graph.borrow().defs[0].borrow().opcode, OpCode::Start);
@@ -131,10 +547,350 @@ mod test_optimizer {
     #[test]
     fn add_compound() {
         let chars = read_chars(Path::new(&format!("{TEST_DIR}/add_compound.c")));
-        let graph = parser::parse(&chars).unwrap();
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
 
         // assert_matches!(graph.borrow().opcode, OpCode::Ret);
         // assert_matches!(graph.borrow().defs[0].borrow().opcode, OpCode::Start);
         // insta::assert_debug_snapshot!(graph);
     }
+
+    fn folded(dir: &str, file: &str) -> Type {
+        let chars = read_chars(Path::new(&format!("{dir}/{file}")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        let typ = graph.arena.typ(graph.arena.def(graph.main().rets[0], 1)).clone();
+        typ
+    }
+
+    // precedence and associativity
+    #[test] fn sub_associative() { assert_eq!(folded(TEST_DIR, "sub_associative.c"), Type::Int(11)); }
+    #[test] fn div_associative() { assert_eq!(folded(TEST_DIR, "div_associative.c"), Type::Int(2)); }
+    #[test] fn mult_add_precedence() { assert_eq!(folded(TEST_DIR, "mult_add_precedence.c"), Type::Int(101)); }
+    #[test] fn mult_add_precedence_multi() { assert_eq!(folded(TEST_DIR, "mult_add_precedence_multi.c"), Type::Int(222)); }
+
+    // unary and parenthesized
+    #[test] fn unary() { assert_eq!(folded(TEST_DIR, "unary.c"), Type::Int(-10)); }
+    #[test] fn paren_precedence() { assert_eq!(folded(TEST_DIR, "paren_precedence.c"), Type::Int(209)); }
+    #[test] fn hex_char() { assert_eq!(folded(TEST_DIR, "hex_char.c"), Type::Int(16 + 97)); }
+
+    // 32-bit two's complement
+    #[test] fn overflow() { assert_eq!(folded(TEST_DIR, "overflow.c"), Type::Int(i32::MIN)); }
+    #[test] fn overflow_mul() { assert_eq!(folded(TEST_DIR, "overflow_mul.c"), Type::Int(i32::MAX - 1)); }
+    #[test] fn modulus() { assert_eq!(folded(TEST_DIR, "mod.c"), Type::Int(9)); }
+    #[test] fn div_zero() { assert_eq!(folded(TEST_DIR, "div_zero.c"), Type::INT); }
+    #[test] fn div_overflow() { assert_eq!(folded(TEST_DIR, "div_overflow.c"), Type::INT); }
+    #[test] fn mod_overflow() { assert_eq!(folded(TEST_DIR, "mod_overflow.c"), Type::INT); }
+
+    // comparisons
+    #[test] fn eq_true() { assert_eq!(folded(CONTROL_DIR, "eq_true.c"), Type::Int(1)); }
+    #[test] fn eq_false() { assert_eq!(folded(CONTROL_DIR, "eq_false.c"), Type::Int(0)); }
+    #[test] fn neq_true() { assert_eq!(folded(CONTROL_DIR, "neq_true.c"), Type::Int(1)); }
+    #[test] fn neq_false() { assert_eq!(folded(CONTROL_DIR, "neq_false.c"), Type::Int(0)); }
+    #[test] fn lt_true() { assert_eq!(folded(CONTROL_DIR, "lt_true.c"), Type::Int(1)); }
+    #[test] fn lteq_true() { assert_eq!(folded(CONTROL_DIR, "lteq_true.c"), Type::Int(1)); }
+    #[test] fn lteq2_true() { assert_eq!(folded(CONTROL_DIR, "lteq2_true.c"), Type::Int(1)); }
+    #[test] fn gt_true() { assert_eq!(folded(CONTROL_DIR, "gt_true.c"), Type::Int(1)); }
+    #[test] fn gteq_true() { assert_eq!(folded(CONTROL_DIR, "gteq_true.c"), Type::Int(1)); }
+    #[test] fn gteq2_true() { assert_eq!(folded(CONTROL_DIR, "gteq2_true.c"), Type::Int(1)); }
+    #[test] fn and_true() { assert_eq!(folded(CONTROL_DIR, "and_true.c"), Type::Int(1)); }
+    #[test] fn and_false() { assert_eq!(folded(CONTROL_DIR, "and_false.c"), Type::Int(0)); }
+    #[test] fn or_true() { assert_eq!(folded(CONTROL_DIR, "or_true.c"), Type::Int(1)); }
+    #[test] fn or_false() { assert_eq!(folded(CONTROL_DIR, "or_false.c"), Type::Int(0)); }
+
+    // ranges
+    #[test] fn mul_zero() { assert_eq!(folded(TEST_DIR, "mul_zero.c"), Type::Int(0)); }
+    #[test] fn range_lt() { assert_eq!(folded(CONTROL_DIR, "range_lt.c"), Type::Int(1)); }
+
+    // idealization
+    fn returned(dir: &str, file: &str) -> (ParseResult, NodeId) {
+        let chars = read_chars(Path::new(&format!("{dir}/{file}")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        let ret = graph.arena.def(graph.main().rets[0], 1);
+        (graph, ret)
+    }
+
+    #[test] fn identity() {
+        let (graph, x) = returned(TEST_DIR, "identity.c");
+        assert_matches!(graph.arena.op(x), OpCode::Proj(1)); // arg
+    }
+    #[test] fn sub_self() { assert_eq!(folded(TEST_DIR, "sub_self.c"), Type::Int(0)); }
+    #[test] fn mult_add_precedence_arg() {
+        // arg*132 + 91
+        let (graph, x) = returned(TEST_DIR, "mult_add_precedence_arg.c");
+        let a = &graph.arena;
+        assert_matches!(a.op(x), OpCode::Add);
+        assert_eq!(*a.typ(a.def(x, 1)), Type::Int(91));
+        let mul = a.def(x, 0);
+        assert_matches!(a.op(mul), OpCode::Mul);
+        assert_matches!(a.op(a.def(mul, 0)), OpCode::Proj(1));
+        assert_eq!(*a.typ(a.def(mul, 1)), Type::Int(132));
+    }
+    #[test] fn reassociate() {
+        // arg*2 + 6
+        let (graph, x) = returned(TEST_DIR, "reassociate.c");
+        let a = &graph.arena;
+        assert_matches!(a.op(x), OpCode::Add);
+        assert_eq!(*a.typ(a.def(x, 1)), Type::Int(6));
+        let mul = a.def(x, 0);
+        assert_matches!(a.op(mul), OpCode::Mul);
+        assert_matches!(a.op(a.def(mul, 0)), OpCode::Proj(1));
+        assert_eq!(*a.typ(a.def(mul, 1)), Type::Int(2));
+    }
+
+    #[test] fn dead_control() {
+        // the call after the first return is dead, but only data is folded, so it's still a call
+        let chars = "int f(int x) { return x; } int main() { return 1; return f(2); }".chars().collect::<Vec<_>>();
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        let a = &graph.arena;
+        let value = a.def(graph.main().rets[1], 1);
+        assert_matches!(a.op(value), OpCode::Proj(1));
+        assert_eq!(*a.typ(value), Type::Top);
+        let end = a.def(value, 0);
+        assert_matches!(a.op(end), OpCode::CallEnd);
+        assert_matches!(a.op(a.def(end, 0)), OpCode::Call(_));
+        assert_eq!(*a.typ(a.def(end, 0)), Type::XCtrl);
+    }
+
+    // global value numbering
+    #[test] fn cse() {
+        let (graph, x) = returned(TEST_DIR, "cse.c");
+        assert_matches!(graph.arena.op(x), OpCode::Mul);
+        assert!(graph.arena.def(x, 0) == graph.arena.def(x, 1)); // a and b are the same division
+    }
+    #[test] fn cse_commute() { assert_eq!(folded(TEST_DIR, "cse_commute.c"), Type::Int(0)); }
+    #[test] fn gvn_edited() {
+        let mut a = Arena::new();
+        let start = a.new_node(OpCode::Start, &[]);
+        let start = a.peephole(start, start);
+        let arg = a.new_node(OpCode::Proj(1), &[start]);
+        let arg = a.peephole(arg, start);
+        let con = |a: &mut Arena, c| { let con = a.new_constant(Type::Int(c), start); a.peephole(con, start) };
+        let add = |a: &mut Arena, y| { let add = a.new_node(OpCode::Add, &[arg, y]); a.peephole(add, start) };
+
+        let (one, two) = (con(&mut a, 1), con(&mut a, 2));
+        let (x, y) = (add(&mut a, one), add(&mut a, one));
+        assert!(x == y);
+        a.set_def(x, 1, two); // x is now arg+2, and no longer arg+1
+        assert!(add(&mut a, one) != x);
+    }
+
+    // iterative peephole
+    #[test] fn iterate() {
+        let chars = read_chars(Path::new(&format!("{CONTROL_DIR}/while_invariant.c")));
+        let mut graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        // x's phi is only found redundant once the loop ends, after x + 2 was built from it
+        let y = graph.arena.def(graph.main().rets[0], 1);
+        assert_matches!(graph.arena.op(y), OpCode::Phi);
+        assert_matches!(graph.arena.op(graph.arena.def(y, 2)), OpCode::Add);
+
+        assert!(optimizer::iterate(&mut graph, 1000).unwrap() > 0);
+        assert_eq!(*graph.arena.typ(graph.arena.def(y, 2)), Type::Int(3));
+        assert_eq!(*graph.arena.typ(y), Type::range(0, 3));
+        assert_eq!(optimizer::iterate(&mut graph, 1000).unwrap(), 0); // a fixed point
+    }
+    #[test] fn iterate_budget() {
+        let chars = read_chars(Path::new(&format!("{CONTROL_DIR}/while_invariant.c")));
+        let mut graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        assert_matches!(optimizer::iterate(&mut graph, 1), Err(optimizer::OptError::Budget { budget: 1 }));
+    }
+
+    // sparse conditional constant propagation
+    #[test] fn sccp() {
+        let chars = read_chars(Path::new(&format!("{CONTROL_DIR}/sccp.c")));
+        let mut graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        // pessimistically, x isn't constant, since x != 1 is unknown around the loop
+        let x = graph.arena.def(graph.main().rets[0], 1);
+        assert_matches!(graph.arena.op(x), OpCode::Phi);
+        assert!(!graph.arena.typ(x).is_constant());
+        assert_eq!(dumper::dump_dot(&chars, &graph).unwrap().matches("label=\"If\"").count(), 2);
+
+        // optimistically, x is never updated, so it's 1 and the inner if is removed (leaving the loop's)
+        assert!(optimizer::sccp(&mut graph, 1000).unwrap() > 0);
+        let x = graph.arena.def(graph.main().rets[0], 1);
+        assert_matches!(graph.arena.op(x), OpCode::Con);
+        assert_eq!(*graph.arena.typ(x), Type::Int(1));
+        assert_eq!(dumper::dump_dot(&chars, &graph).unwrap().matches("label=\"If\"").count(), 1);
+    }
+    #[test] fn sccp_dead_control() {
+        // optimistically, f is never called. only data is replaced by constants, while the dead call is cut from f
+        let src = "int f(int x) { return x; } int main() { int x = 1; int i = 0; while (i < arg) { if (x != 1) x = f(2); i = i + 1; } return x; }";
+        let mut graph = parser::parse(&src.chars().collect::<Vec<_>>(), OptLevel::O1).unwrap();
+        assert_eq!(graph.arena.defs(graph.fun("f").unwrap().fun).len(), 1);
+        assert!(optimizer::sccp(&mut graph, 1000).unwrap() > 0);
+
+        let a = &graph.arena;
+        assert_eq!(*a.typ(a.def(graph.main().rets[0], 1)), Type::Int(1));
+        assert_eq!(a.defs(graph.fun("f").unwrap().fun).len(), 0);
+        assert!(a.ids().filter(|n| a.op(*n) == OpCode::Con).all(|n| matches!(a.typ(n), Type::Int(_))));
+    }
+    #[test] fn sccp_budget() {
+        let chars = read_chars(Path::new(&format!("{CONTROL_DIR}/sccp.c")));
+        let mut graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        assert_matches!(optimizer::sccp(&mut graph, 1), Err(optimizer::OptError::Budget { budget: 1 }));
+    }
+
+    // dead node elimination
+    #[test] fn eliminate_dead_ret() {
+        let chars = read_chars(Path::new(&format!("{CONTROL_DIR}/branch.c")));
+        let mut graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        assert_eq!(graph.main().rets.len(), 2);
+        assert_eq!(graph.arena.defs(graph.stop).len(), 2);
+
+        assert!(optimizer::eliminate(&mut graph) > 0);
+        assert_eq!(graph.main().rets.len(), 1);
+        assert_eq!(format!("{:?}", graph.arena.at(graph.arena.def(graph.main().rets[0], 1))), "9");
+        assert_eq!(graph.arena.defs(graph.stop).len(), 1);
+    }
+    #[test] fn eliminate() {
+        let chars = read_chars(Path::new(&format!("{CONTROL_DIR}/ifels_els.c")));
+        let mut graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        optimizer::sccp(&mut graph, 1000).unwrap();
+        optimizer::iterate(&mut graph, 1000).unwrap();
+        assert!(optimizer::eliminate(&mut graph) > 0);
+        assert_eq!(optimizer::eliminate(&mut graph), 0);
+
+        // every node is live, and every use is of a live node
+        let (a, live) = (&graph.arena, optimizer::nodes(&graph.arena));
+        for &n in &live {
+            assert!(!optimizer::is_dead(a, n), "{} is dead", a.at(n));
+            for u in a.users(n) { assert!(a.is_alive(u), "{} is used by dead {}", a.at(n), a.at(u)) }
+        }
+        assert!(!live.iter().any(|n| *a.typ(*n) == Type::XCtrl));
+    }
+    #[test] fn eliminate_infinite() {
+        // the loop returns nowhere, but is anchored to stop by the exit of a never, which isn't a return
+        let chars = read_chars(Path::new(&format!("{CONTROL_DIR}/while_forever.c")));
+        let mut graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        optimizer::sccp(&mut graph, 1000).unwrap();
+        optimizer::iterate(&mut graph, 1000).unwrap();
+        optimizer::eliminate(&mut graph);
+
+        let a = &graph.arena;
+        let head = a.ids().find(|n| a.op(*n) == OpCode::Loop).unwrap();
+        let exit = a.defs(graph.stop)[0];
+        assert!(graph.main().rets.is_empty());
+        assert_eq!(a.defs(graph.stop), [exit]);
+        assert_matches!(a.op(exit), OpCode::Proj(0));
+        assert_matches!(a.op(a.def(exit, 0)), OpCode::Never);
+        assert_eq!(a.def(a.def(exit, 0), 0), head);
+        assert!(scheduler::schedule(a, graph.main()).iter().any(|b| b.head() == head && b.loop_depth == 1));
+
+        // and stays anchored, since the never isn't folded away
+        optimizer::sccp(&mut graph, 1000).unwrap();
+        optimizer::iterate(&mut graph, 1000).unwrap();
+        assert_eq!(optimizer::eliminate(&mut graph), 0);
+        assert_eq!(graph.arena.defs(graph.stop), [exit]);
+        assert_matches!(graph.arena.op(graph.arena.def(exit, 0)), OpCode::Never);
+
+        // a function that does return keeps only its own returns
+        let mut graph = parser::parse(&"int main() { if (arg) { while (1) { } } return 0; }".chars().collect::<Vec<_>>(), OptLevel::O1).unwrap();
+        optimizer::sccp(&mut graph, 1000).unwrap();
+        optimizer::iterate(&mut graph, 1000).unwrap();
+        optimizer::eliminate(&mut graph);
+        assert_eq!(graph.main().rets.len(), 1);
+        assert_eq!(graph.arena.defs(graph.stop).len(), 2);
+    }
+    #[test] fn unused_division() {
+        // a division that may trap is kept though its result is unused, unless it's proven not to
+        for (src, kept) in [("int main() { int x = 1 / 0; return 0; }", true), ("int main() { int x = 1 / arg; return 0; }", true),
+                            ("int main() { int x = arg % 2; return 0; }", false)] {
+            let mut graph = parser::parse(&src.chars().collect::<Vec<_>>(), OptLevel::O1).unwrap();
+            let div = |a: &Arena| a.ids().any(|n| matches!(a.op(n), OpCode::Div | OpCode::Mod));
+            assert_eq!(div(&graph.arena), kept);
+            optimizer::sccp(&mut graph, 1000).unwrap();
+            optimizer::iterate(&mut graph, 1000).unwrap();
+            optimizer::eliminate(&mut graph);
+            assert_eq!(div(&graph.arena), kept);
+            let blocks = scheduler::schedule(&graph.arena, graph.main());
+            assert_eq!(blocks.iter().flat_map(|b| &b.nodes).any(|n| matches!(graph.arena.op(*n), OpCode::Div | OpCode::Mod)), kept);
+        }
+    }
+    #[test] fn eliminate_schedule() {
+        // functions without a live return keep their fun, whose id isn't reused by the nodes created after the sweep
+        for src in ["int main() { }", "int main() { while (1) { } return 0; }", "int f() { } int main() { return 1; }"] {
+            let mut graph = parser::parse(&src.chars().collect::<Vec<_>>(), OptLevel::O1).unwrap();
+            optimizer::sccp(&mut graph, 1000).unwrap();
+            optimizer::iterate(&mut graph, 1000).unwrap();
+            optimizer::eliminate(&mut graph);
+            graph.arena.new_constant(Type::Int(0), graph.start);
+            for f in &graph.funs {
+                assert_matches!(graph.arena.op(f.fun), OpCode::Fun(_));
+                assert!(!scheduler::schedule(&graph.arena, f).is_empty());
+            }
+        }
+    }
+    #[test] fn deep() {
+        // a chain of x's updates is as deep as the program is long, and is freed and scheduled without recursion
+        let src = format!("int main() {{ int x = arg; {} return x; }}", "x = x * 3 + arg; ".repeat(50_000));
+        let mut graph = parser::parse(&src.chars().collect::<Vec<_>>(), OptLevel::O1).unwrap();
+        assert!(graph.arena.len() > 100_000);
+        optimizer::sccp(&mut graph, usize::MAX).unwrap();
+        optimizer::iterate(&mut graph, usize::MAX).unwrap();
+        optimizer::eliminate(&mut graph);
+        assert!(graph.arena.len() > 100_000);
+        let blocks = scheduler::schedule(&graph.arena, graph.main());
+        assert_eq!(blocks.len(), 1);
+        assert!(blocks[0].nodes.len() > 100_000);
+    }
+
+    // optimization levels
+    #[test] fn frontend_output() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/mult_add_precedence_multi.c")));
+        let (mut raw, opt) = (parser::parse(&chars, OptLevel::O0).unwrap(), parser::parse(&chars, OptLevel::O1).unwrap());
+        assert!(dumper::dump_dot(&chars, &raw).unwrap().contains("Mul"));
+        assert!(!dumper::dump_dot(&chars, &opt).unwrap().contains("Mul"));
+
+        // the raw graph is typed, but only folded by the optimizer
+        let x = raw.arena.def(raw.main().rets[0], 1);
+        assert_matches!(raw.arena.op(x), OpCode::Add);
+        assert_eq!(*raw.arena.typ(x), Type::Int(222));
+        assert!(optimizer::iterate(&mut raw, 1000).unwrap() > 0);
+        let x = raw.arena.def(raw.main().rets[0], 1);
+        assert_matches!(raw.arena.op(x), OpCode::Con);
+        assert_eq!(*raw.arena.typ(x), Type::Int(222));
+        assert!(!dumper::dump_dot(&chars, &raw).unwrap().contains("Mul"));
+    }
+
+    // lattice laws, checked exhaustively over a sample of each kind of type
+    fn samples() -> Vec<Type> {
+        let ints = [Type::Int(0), Type::Int(1), Type::Int(-5), Type::BOOL, Type::INT, Type::Range(3, 10), Type::Range(10, 3), Type::INT.dual()];
+        let mut ts = vec![Type::Top, Type::Bot, Type::Ctrl, Type::XCtrl];
+        ts.extend(ints.iter().cloned());
+        ts.extend([Type::Tup(vec![Type::Ctrl, Type::INT]), Type::Tup(vec![Type::XCtrl, Type::Int(1)]), Type::Tup(vec![Type::Ctrl, Type::Range(10, 3)])]);
+        ts
+    }
+
+    #[test] fn meet_laws() {
+        let ts = samples();
+        for x in &ts {
+            assert_eq!(x.meet(x), *x, "idempotence of {x}");
+            assert_eq!(x.meet(&Type::Top), *x);
+            assert_eq!(x.meet(&Type::Bot), Type::Bot);
+            for y in &ts {
+                assert_eq!(x.meet(y), y.meet(x), "commutativity of {x} and {y}");
+                assert_eq!(x.meet(&x.join(y)), *x, "absorption of {x} and {y}");
+                for z in &ts { assert_eq!(x.meet(y).meet(z), x.meet(&y.meet(z)), "associativity of {x}, {y} and {z}"); }
+            }
+        }
+    }
+
+    #[test] fn dual_laws() {
+        let ts = samples();
+        for x in &ts {
+            assert_eq!(x.dual().dual(), *x, "involution of {x}");
+            for y in &ts { assert_eq!(x.meet(y).dual(), x.dual().join(&y.dual()), "de morgan of {x} and {y}"); }
+        }
+    }
+
+    #[test] fn ranges() {
+        assert_eq!(Type::Int(0).meet(&Type::Int(1)), Type::BOOL);
+        assert_eq!(Type::Int(-5).meet(&Type::Range(3, 10)), Type::Range(-5, 10));
+        assert_eq!(Type::Range(10, 3).meet(&Type::Int(7)), Type::range(7, 7));
+        assert_eq!(Type::Range(10, 3).meet(&Type::Int(1)), Type::Range(1, 3));
+        assert_eq!(Type::INT.dual().meet(&Type::BOOL), Type::BOOL);
+        assert_eq!(Type::BOOL.join(&Type::Range(1, 9)), Type::Int(1));
+        assert_eq!(Type::INT.to_string(), "int");
+        assert_eq!(Type::Range(3, 10).to_string(), "[3, 10]");
+    }
 }
@@ -1,325 +1,874 @@
-use std::{collections::HashMap, iter};
-use crate::son::{optimizer::Type, DefEdge, OpCode};
+use std::{collections::{HashMap, HashSet}, fmt::Display, fs, mem, path::{Path, PathBuf}, str};
+use crate::son::{diagnostic::Diagnostic, optimizer::{OptLevel, Type}, Arena, NodeId, OpCode};
 use thiserror::Error;
 
+// NB: every error is located by the span of the offending token (or the end of the file)
 #[derive(Error, Debug)] pub enum ParseError {
-    #[error("lex error")] LexError(#[from] LexError),
-    #[error("parse error (expected {expected:?}, found {actual:?})")] Mismatch { expected: String, actual: String },
-    #[error("scope error")] ScopeError(#[from] ScopeError),
+    #[error(transparent)] LexError(#[from] LexError),
+    #[error("expected {expected}, found {}", found(.actual))] Mismatch { expected: String, actual: String, span: Span },
+    #[error("{err}")] ScopeError { err: ScopeError, span: Span },
 }
 
-pub struct ParseResult { pub start: DefEdge, pub stop: DefEdge, pub scope: Scope }
-pub fn parse(chars: &[char]) -> Result<ParseResult, ParseError> {
-    let tokens = lex(chars)?;
-    let (start, scope) = (DefEdge::new(OpCode::Start), Scope::new());
-    let mut parser = Parser::new(start, scope);
-    let graph = parser.parse(&tokens, false)?;
-    Ok(ParseResult { start: parser.start, stop: graph, scope: parser.scope })
+impl ParseError {
+    pub fn span(&self) -> Span { match self {
+        Self::LexError(LexError::UnknownToken { span, .. } | LexError::InvalidLiteral { span, .. } | LexError::OutOfRange { span, .. }) => *span,
+        Self::LexError(LexError::UnterminatedComment { span } | LexError::InvalidDirective { span, .. } | LexError::UnresolvedUse { span, .. }) => *span,
+        Self::LexError(LexError::InvalidEncoding { span }) => *span,
+        Self::Mismatch { span, .. } | Self::ScopeError { span, .. } => *span,
+    }}
+
+    // the offending identifier (or lexeme)
+    pub fn ident(&self) -> &str { match self {
+        Self::LexError(LexError::UnknownToken { unknown: ident, .. } | LexError::InvalidLiteral { literal: ident, .. } | LexError::OutOfRange { literal: ident, .. }) => ident,
+        Self::LexError(LexError::InvalidDirective { directive: ident, .. } | LexError::UnresolvedUse { name: ident, .. }) => ident,
+        Self::LexError(LexError::UnterminatedComment { .. }) => "/*",
+        Self::LexError(LexError::InvalidEncoding { .. }) => "",
+        Self::Mismatch { actual: ident, .. } => ident,
+        Self::ScopeError { err, .. } => err.ident(),
+    }}
+
+    pub fn diagnose(&self, file: &str, chars: &[char]) -> Diagnostic { Diagnostic::new(file, chars, self.span(), self.ident(), self.to_string()) }
+}
+
+fn plural(n: usize, noun: &str) -> String { if n == 1 { format!("1 {noun}") } else { format!("{n} {noun}s") } }
+fn found(actual: &str) -> String { if actual.is_empty() { "end of file".to_owned() } else { format!("`{actual}`") } }
+
+// NB: stop collects the returns of every function (and eliminate adds the never taken exits of infinite loops),
+//     so the live graph is everything reachable from it (see eliminate). the arena holds every node of the graph
+pub struct ParseResult { pub arena: Arena, pub start: NodeId, pub stop: NodeId, pub funs: Vec<Function>, pub scope: Scope }
+// NB: every error in the file is reported, in source order. lex errors are reported before parsing,
+//     since the parser would only trip over the tokens they leave out.
+//     without a file (and search path) to resolve them from, #use directives are errors.
+//     at -O0 nodes are built without peepholes, keeping the frontend's graph (see OptLevel)
+pub fn parse(chars: &[char], opt: OptLevel) -> Result<ParseResult, Vec<ParseError>> {
+    let tokens = lex(chars).map_err(|errs| errs.into_iter().map(ParseError::from).collect::<Vec<_>>())?;
+    let uses = tokens.iter().filter(|t| matches!(t.typ, TT::UseLib | TT::UseFile));
+    let errs = uses.map(|t| LexError::UnresolvedUse { name: used(t).to_owned(), span: t.span }.into()).collect::<Vec<_>>();
+    if !errs.is_empty() { return Err(errs) }
+    parse_tokens(&tokens, opt)
+}
+
+pub fn parse_file(loader: &mut Loader, path: &Path, opt: OptLevel) -> Result<ParseResult, Vec<ParseError>> {
+    let tokens = loader.lex(path)?;
+    parse_tokens(&tokens, opt)
+}
+
+// NB: nodes the parser built and then dropped (replaced by peepholes, or rebound in the scope) are freed
+//     once parsing is done, keeping the nodes the result holds, the divisions that may trap (and their defs)
+fn parse_tokens(tokens: &[Token], opt: OptLevel) -> Result<ParseResult, Vec<ParseError>> {
+    let mut arena = Arena::new();
+    let start = arena.new_node(OpCode::Start, &[]);
+    let (start, scope) = (arena.peephole(start, start), Scope::new(&mut arena));
+    let mut parser = Parser::new(arena, start, scope, opt);
+    parser.parse(tokens, false);
+    if !parser.errors.is_empty() { return Err(parser.errors) }
+    let Parser { mut arena, start, funs, scope, .. } = parser;
+    let rets = funs.iter().flat_map(|f| f.rets.iter().copied()).collect::<Vec<_>>();
+    let stop = arena.new_node(OpCode::Stop, &rets);
+    let held = funs.iter().flat_map(|f| [f.fun].into_iter().chain(f.parms.iter().copied()));
+    let effects = arena.effects();
+    arena.sweep([start, stop, scope.lookup].into_iter().chain(held).chain(effects).collect::<Vec<_>>());
+    Ok(ParseResult { arena, start, stop, funs, scope })
 }
 
+impl ParseResult {
+    pub fn fun(&self, name: &str) -> Option<&Function> { self.funs.iter().find(|f| f.name == name) }
+    pub fn main(&self) -> &Function { self.fun("main").expect("parse checks main is defined") }
+}
+
+// NB: a function's entry is its Fun region, whose defs are the calls to it (and start, for main).
+//     parms are phis of the fun region: their defs are the arguments of each call, in the same order.
+//     functions are created on first mention, so calls can refer to functions defined later in the file
+pub struct Function { pub name: String, pub fun: NodeId, pub parms: Vec<NodeId>, pub rets: Vec<NodeId>, defined: bool }
+
+// NB: binary operators and their precedence, from loosest to tightest binding. all are left associative.
+//     && and || short circuit, and > and >= are lowered to < and <= with swapped operands
+#[derive(Clone, Copy)] enum Infix { And, Or, Node(OpCode), Swapped(OpCode) }
+const INFIX: &[(TT, u8, Infix)] = &[
+    (TT::BarBar, 1, Infix::Or),
+    (TT::AmpAmp, 2, Infix::And),
+    (TT::EqualsEquals, 3, Infix::Node(OpCode::Eq)), (TT::BangEquals, 3, Infix::Node(OpCode::Ne)),
+    (TT::LeftAngleBracket, 4, Infix::Node(OpCode::Lt)), (TT::LeftAngleBracketEquals, 4, Infix::Node(OpCode::Le)),
+    (TT::RightAngleBracket, 4, Infix::Swapped(OpCode::Lt)), (TT::RightAngleBracketEquals, 4, Infix::Swapped(OpCode::Le)),
+    (TT::Plus, 5, Infix::Node(OpCode::Add)), (TT::Minus, 5, Infix::Node(OpCode::Sub)),
+    (TT::Star, 6, Infix::Node(OpCode::Mul)), (TT::Slash, 6, Infix::Node(OpCode::Div)), (TT::Percent, 6, Infix::Node(OpCode::Mod)),
+];
+
 // NB: temporary state created for recursive descent's access to &start (peepholes) and scope (varapp &/vardef &mut)
-struct Parser { start: DefEdge, scope: Scope }
+//     rets keeps every return of the current function alive, since returns nested in branches have no uses.
+//     calls are linked to their callee once every function is parsed, with their arguments counted as parsed
+//     (not from the call node, which peepholes may rewrite). eof locates errors at the end of the file,
+//     and errors are collected while the parser recovers (see sync)
+struct Parser { arena: Arena, start: NodeId, scope: Scope, opt: OptLevel, rets: Vec<NodeId>, funs: Vec<Function>, fids: HashMap<String, usize>, calls: Vec<(usize, NodeId, NodeId, usize, Span)>, eof: Span, errors: Vec<ParseError> }
 impl Parser {
-    fn new(start: DefEdge, scope: Scope) -> Self { Self { start, scope } }
+    fn new(arena: Arena, start: NodeId, scope: Scope, opt: OptLevel) -> Self {
+        let eof = Span { start: 0, end: 0, line: 1, col: 1, src: 0 };
+        Self { arena, start, scope, opt, rets: Vec::new(), funs: Vec::new(), fids: HashMap::new(), calls: Vec::new(), eof, errors: Vec::new() }
+    }
+
+    fn peephole(&mut self, node: NodeId) -> NodeId { self.arena.peephole_at(node, self.start, self.opt) }
+    fn node(&mut self, op: OpCode, defs: &[NodeId]) -> NodeId { self.arena.new_node(op, defs) }
+    fn ctrl(&self) -> NodeId { self.scope.ctrl(&self.arena) }
+    fn set_ctrl(&mut self, ctrl: NodeId) -> () { self.scope.set_ctrl(&mut self.arena, ctrl) }
+    fn pop_nv(&mut self) -> () { self.scope.pop_nv(&mut self.arena) }
 
     // NB. each function in the parser will parse either:
     //     a. match: match tokens(first, rest) first.typ { TT::Foo => {}, TT::Bar => {}, TT::Baz => {} }
-    //     b. assert: Self::require(tokens, TT:Foo), Self::require(tokens, TT:Bar), Self::require(tokens, TT:Baz)
-    fn parse(&mut self, tokens: &[Token], _dump: bool) -> Result<DefEdge, ParseError> {
-        let r = tokens;
-        let (_, r) = Self::require(r, TT::KeywordInt)?;
-        let (_, r) = Self::require(r, TT::Alias)?;
-        let (_, r) = Self::require(r, TT::PuncLeftParen)?;
-        let (_, r) = Self::require(r, TT::PuncRightParen)?;
-
-        let (_, r) = Self::require(r, TT::PuncLeftBrace)?;
+    //     b. assert: self.require(tokens, TT:Foo), self.require(tokens, TT:Bar), self.require(tokens, TT:Baz)
+    fn parse(&mut self, tokens: &[Token], _dump: bool) -> () {
+        if let Some(t) = tokens.last() { self.eof = Span { start: t.span.end, end: t.span.end, line: t.span.line, col: t.span.col + t.lexeme.chars().count(), src: t.span.src } }
         self.scope.push_nv(); // global scope
-        // scope.write(CTRL.to_owned(), Proj::new(*START.clone(), 0));
-        // scope.write(ARG.to_owned(), Proj::new(*START.clone(), 1));
-        let (block, r) = self.parse_block(r)?;
-        self.scope.pop_nv();
+        let (ctrl, arg) = (self.node(OpCode::Proj(0), &[self.start]), self.node(OpCode::Proj(1), &[self.start]));
+        let (ctrl, arg) = (self.peephole(ctrl), self.peephole(arg));
+        let _ = self.scope.vardef(&mut self.arena, CTRL, ctrl); // NB: the global nv is empty, so these can't fail
+        let _ = self.scope.vardef(&mut self.arena, ARG, arg);
 
-        let (_, r) = Self::require(r, TT::PuncRightBrace)?;
-        // try_convert block's Rc<dyn Instr> -> Rc<Return>
+        let (depth, entry, mut r) = (self.scope.nvs.len(), self.ctrl(), tokens);
+        while !r.is_empty() {
+            r = match self.parse_fun(r) {
+                Ok(r) => r,
+                Err(e) => {
+                    self.recover(e, depth, entry);
+                    self.rets.clear();
+                    Self::sync(&r[1..]) // NB: skipping a token makes progress on a stray `}`
+                },
+            };
+        }
+        self.pop_nv();
 
         // if dump {}
-        if r.is_empty() { Ok(block) } else { Err(ParseError::Mismatch { expected: "empty token stream".to_string(), actual: format!("{:?}", r) }) }
+        if !self.fids.contains_key("main") { self.errors.push(ScopeError::NotFound { alias: "main".to_owned() }.at(self.eof)) }
+        self.link()
+    }
+
+    // NB: panic mode recovery. sync skips the rest of a broken statement (or function): up to and including
+    //     its `;` or the `}` closing its block, and stops before the `}` closing the enclosing block.
+    //     the broken statement's nvs are popped, so following statements see the enclosing block's bindings.
+    //     the two `;`s of a for's header don't end it, so recovery never resumes inside the header
+    fn sync(tokens: &[Token]) -> &[Token] {
+        let (mut depth, mut header) = (0, if matches!(tokens, [f, ..] if f.typ == TT::KeywordFor) { 2 } else { 0 });
+        for (i, t) in tokens.iter().enumerate() {
+            match t.typ {
+                TT::PuncLeftBrace => depth += 1,
+                TT::PuncSemiColon if depth == 0 && header > 0 => header -= 1,
+                TT::PuncRightBrace if depth == 0 => return &tokens[i..],
+                TT::PuncRightBrace => { depth -= 1; if depth == 0 { return &tokens[i + 1..] } },
+                TT::PuncSemiColon if depth == 0 => return &tokens[i + 1..],
+                _ => {},
+            }
+        }
+        &tokens[tokens.len()..]
+    }
+
+    fn recover(&mut self, e: ParseError, depth: usize, ctrl: NodeId) -> () {
+        self.errors.push(e);
+        while self.scope.nvs.len() > depth { self.pop_nv(); }
+        self.set_ctrl(ctrl);
+    }
+
+    fn parse_fun<'a>(&mut self, tokens: &'a [Token]) -> Result<&'a [Token], ParseError> {
+        let (_, r) = self.require(tokens, TT::KeywordInt)?;
+        let (name, r) = self.require(r, TT::Alias)?;
+        let (_, mut r) = self.require(r, TT::PuncLeftParen)?;
+        let mut params = Vec::new();
+        while let [f, ..] = r {
+            if f.typ == TT::PuncRightParen { break }
+            let (_, _r) = self.require(if params.is_empty() { r } else { self.require(r, TT::PuncComma)?.1 }, TT::KeywordInt)?;
+            let (param, _r) = self.require(_r, TT::Alias)?;
+            params.push(param);
+            r = _r;
+        }
+        let (_, r) = self.require(r, TT::PuncRightParen)?;
+
+        let fid = self.fid(&name.lexeme);
+        if self.funs[fid].defined { return Err(ScopeError::DoubleDefine { alias: name.lexeme.to_owned() }.at(name.span)) }
+        let (fun, entry) = (self.funs[fid].fun, self.ctrl());
+        if name.lexeme == "main" { self.arena.add_def(fun, entry); }
+
+        self.scope.push_nv(); // parameter scope
+        self.set_ctrl(fun);
+        let mut parms = Vec::new();
+        for (i, param) in params.iter().enumerate() {
+            let parm = self.node(OpCode::Parm(i), &[fun]);
+            let parm = self.peephole(parm);
+            self.scope.vardef(&mut self.arena, &param.lexeme, parm).map_err(|e| e.at(param.span))?;
+            parms.push(parm);
+        }
+
+        let (_, r) = self.require(r, TT::PuncLeftBrace)?;
+        let (_, r) = self.parse_block(r)?;
+        let (_, r) = self.require(r, TT::PuncRightBrace)?;
+        self.pop_nv();
+        self.set_ctrl(entry);
+
+        let f = &mut self.funs[fid];
+        (f.parms, f.rets, f.defined) = (parms, mem::take(&mut self.rets), true);
+        Ok(r)
+    }
+
+    // the id of a function, declaring it on first mention
+    fn fid(&mut self, name: &str) -> usize {
+        if let Some(fid) = self.fids.get(name) { return *fid }
+        let fid = self.funs.len();
+        let fun = self.node(OpCode::Fun(fid), &[]);
+        let fun = self.peephole(fun);
+        self.funs.push(Function { name: name.to_owned(), fun, parms: Vec::new(), rets: Vec::new(), defined: false });
+        self.fids.insert(name.to_owned(), fid);
+        fid
+    }
+
+    // NB: each call becomes a control input of its callee's fun region (and its arguments inputs of the parms),
+    //     and the callee's returns become inputs of the call end. calls under dead control are checked, but never reach the callee
+    fn link(&mut self) -> () {
+        for (fid, call, end, args, span) in mem::take(&mut self.calls) {
+            let f = &self.funs[fid];
+            if !f.defined { self.errors.push(ScopeError::NotFound { alias: f.name.to_owned() }.at(span)); continue }
+            if args != f.parms.len() { self.errors.push(ScopeError::ArityMismatch { name: f.name.to_owned(), expected: f.parms.len(), actual: args }.at(span)); continue }
+            if *self.arena.typ(call) == Type::XCtrl { continue }
+
+            self.arena.add_def(f.fun, call);
+            for (i, &parm) in f.parms.iter().enumerate() { let arg = self.arena.def(call, i + 1); self.arena.add_def(parm, arg); }
+            for &ret in &f.rets { self.arena.add_def(end, ret); }
+        }
     }
 
     // NB: lexical scope ==> nv's are only pushed/popped in parse_block
-    fn parse_block<'a>(&mut self, tokens: &'a [Token]) -> Result<(DefEdge, &'a [Token]), ParseError> {
+    fn parse_block<'a>(&mut self, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
         self.scope.push_nv();
         let (mut output, mut r) = (None, tokens);
-        while let Ok((stmt, _r)) = self.parse_stmt(r) {
-            output = Some(stmt);
-            r = _r;
+        while let [f, ..] = r {
+            if f.typ == TT::PuncRightBrace { break }
+            let (depth, ctrl) = (self.scope.nvs.len(), self.ctrl());
+            r = match self.parse_stmt(r) {
+                Ok((stmt, _r)) => { output = Some(stmt); _r },
+                Err(e) => { self.recover(e, depth, ctrl); Self::sync(r) },
+            };
         }
-        self.scope.pop_nv();
-        Ok((output.unwrap(), r))
+        self.pop_nv();
+        Ok((output.unwrap_or_else(|| self.ctrl()), r))
     }
 
-    fn parse_stmt<'a>(&mut self, tokens: &'a [Token]) -> Result<(DefEdge, &'a [Token]), ParseError> {
+    fn parse_stmt<'a>(&mut self, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
         match tokens {
-            [] => Err(ParseError::Mismatch { expected: "".to_string(), actual: "".to_string() }),
+            [] => Err(self.mismatch("a statement", None)),
             [f, r @ ..] => match f.typ {
                 TT::KeywordInt => {
-                    let (alias, r) = Self::require(r, TT::Alias)?;
-                    let (_, r) = Self::require(r, TT::Equals)?;
-                    let (expr, r) = self.parse_expr(r)?;
-                    let (_, r) = Self::require(r, TT::PuncSemiColon)?;
-
-                    let _ = self.scope.vardef(&alias.lexeme, expr.clone())?;
+                    let (expr, r) = self.parse_decl(tokens)?;
+                    let (_, r) = self.require(r, TT::PuncSemiColon)?;
                     Ok((expr, r))
                 }
-                // TT::KeywordIf => {
-                //     let (pred, r) = parse_expr(r)?;
-
-                //     let branch = Branch::new(scope.read_ctrl(), pred);
-                //     let left = Proj::new(branch.clone(), 0).peephole(*START.clone());
-                //     let right = Proj::new(branch, 1).peephole(*START.clone());
-                //     let scope_og = Rc::new((*scope).clone()); // TODO: need ascii debugger here to verify
-
-                //     // NB: because condtionals are statements and not expressions
-                //     //     in C, the return of parse_stmts are not bound and ignored
-
-                //     scope.write_ctrl(left); // 1. set ctrl
-                //     let (_, r) = parse_stmt(r)?; // 2. parse
-                //     let scope_left = Rc::new((*scope).clone()); // 3. alias scope
+                TT::PuncLeftBrace => {
+                    let (block, r) = self.parse_block(r)?;
+                    let (_, r) = self.require(r, TT::PuncRightBrace)?;
+                    Ok((block, r))
+                }
+                TT::KeywordIf => {
+                    let (_, r) = self.require(r, TT::PuncLeftParen)?;
+                    let (pred, r) = self.parse_expr(r)?;
+                    let (_, r) = self.require(r, TT::PuncRightParen)?;
+                    let (left, right) = self.branch(pred);
 
-                //     scope = scope_og; // reset
+                    // NB: because condtionals are statements and not expressions
+                    //     in C, the return of parse_stmts are not bound and ignored
+                    let scope_og = self.scope.dup(&mut self.arena, false);
+                    self.set_ctrl(left); // 1. set ctrl
+                    let (_, r) = self.parse_body(r)?; // 2. parse
+                    let scope_left = mem::replace(&mut self.scope, scope_og); // 3. alias scope
 
-                //     scope.write_ctrl(right); // 1. set ctrl
-                //     if r.len() > 1 && r[0].typ == TT::KeywordEls { let (_, r) = parse_stmt(r)?; }; // 2. parse
-                //     let scope_right = Rc::new((*scope).clone()); // 3. alias scope
+                    self.set_ctrl(right); // 1. set ctrl
+                    let r = match r { // 2. parse
+                        [f, _r @ ..] if f.typ == TT::KeywordEls => self.parse_body(_r)?.1,
+                        _ => r,
+                    };
+                    let scope_right = mem::replace(&mut self.scope, scope_left); // 3. alias scope
 
-                //     let region = scope_left.merge(&scope_right);
-                //     scope.write_ctrl(region.clone());
-                //     Ok((region, r))
-                // },
+                    let region = self.scope.merge(&mut self.arena, scope_right, self.start, self.opt);
+                    Ok((region, r))
+                },
+                TT::KeywordWhile => {
+                    let (_, r) = self.require(r, TT::PuncLeftParen)?;
+                    self.parse_loop(r, false)
+                }
+                TT::KeywordFor => {
+                    let (_, r) = self.require(r, TT::PuncLeftParen)?;
+                    self.scope.push_nv(); // the init variable is only visible inside the loop
+                    let r = match r {
+                        [f, ..] if f.typ == TT::KeywordInt => self.parse_decl(r)?.1,
+                        [f, ..] if f.typ == TT::PuncSemiColon => r,
+                        _ => self.parse_simple(r)?.1,
+                    };
+                    let (_, r) = self.require(r, TT::PuncSemiColon)?;
+                    let (loop_, r) = self.parse_loop(r, true)?;
+                    self.pop_nv();
+                    Ok((loop_, r))
+                }
+                TT::Alias => {
+                    let (expr, r) = self.parse_simple(tokens)?;
+                    let (_, r) = self.require(r, TT::PuncSemiColon)?;
+                    Ok((expr, r))
+                }
                 TT::KeywordRet => {
                     let (expr, r) = self.parse_expr(r)?;
-                    let (_, r) = Self::require(r, TT::PuncSemiColon)?;
-                    let ret = DefEdge::new(OpCode::Ret);
-                    let _ = DefEdge::add_def(&ret, &self.start);
-                    let _ = DefEdge::add_def(&ret, &expr);
+                    let (_, r) = self.require(r, TT::PuncSemiColon)?;
+                    let ret = self.node(OpCode::Ret, &[self.ctrl(), expr]);
+                    self.rets.push(ret);
 
+                    // NB: code following a return is unreachable
+                    let dead = self.con(Type::XCtrl);
+                    self.set_ctrl(dead);
                     Ok((ret, r))
                 }
-                t => Err(ParseError::Mismatch {
-                    expected: format!("expected: {:?} got: {:?}", TT::KeywordRet, t),
-                    actual: f.lexeme.to_owned(),
-                }),
+                _ => Err(self.mismatch("a statement", Some(f))),
             },
         }
     }
 
-    fn parse_expr<'a>(&self, tokens: &'a [Token]) -> Result<(DefEdge, &'a [Token]), ParseError> {
-        self.parse_term(tokens)
+    // NB: the body of an if, else or loop is a scope of its own, even when it isn't a block (a declaration)
+    fn parse_body<'a>(&mut self, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
+        self.scope.push_nv();
+        let body = self.parse_stmt(tokens);
+        self.pop_nv();
+        body
     }
 
-    fn parse_term<'a>(&self, tokens: &'a [Token]) -> Result<(DefEdge, &'a [Token]), ParseError> {
-        let (x, r) = self.parse_factor(tokens)?;
+    fn parse_decl<'a>(&mut self, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
+        let (_, r) = self.require(tokens, TT::KeywordInt)?;
+        let (alias, r) = self.require(r, TT::Alias)?;
+        let (_, r) = self.require(r, TT::Equals)?;
+        let (expr, r) = match self.parse_expr(r) {
+            Ok(expr) => expr,
+            Err(e) => { // NB: a broken declaration still declares its variable, so its uses don't cascade into more errors
+                let bot = self.con(Type::Bot);
+                let _ = self.scope.vardef(&mut self.arena, &alias.lexeme, bot);
+                return Err(e)
+            },
+        };
+
+        self.scope.vardef(&mut self.arena, &alias.lexeme, expr).map_err(|e| e.at(alias.span))?;
+        Ok((expr, r))
+    }
 
-        match r {
-            [] => panic!(),
-            [f, _r @ ..] => match f.typ {
-                TT::Plus => {
-                    let (y, r) = self.parse_term(_r)?;
-                    let add = DefEdge::new(OpCode::Add);
-                    let (_, _) = (add.add_def(&x), add.add_def(&y));
-                    Ok((add.peephole(&self.start), r))
+    // NB: simple statements update an existing variable, and are shared by
+    //     statements (followed by a semicolon) and the step of a for loop
+    fn parse_simple<'a>(&mut self, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
+        let (alias, r) = self.require(tokens, TT::Alias)?;
+        let (expr, r) = match r {
+            [] => return Err(self.mismatch("an assignment", None)),
+            [f, r @ ..] => match f.typ {
+                TT::Equals => self.parse_expr(r)?,
+                TT::PlusEquals | TT::MinusEquals | TT::StarEquals | TT::SlashEquals | TT::PercentEquals => {
+                    let op = match f.typ { TT::PlusEquals => OpCode::Add, TT::MinusEquals => OpCode::Sub, TT::StarEquals => OpCode::Mul, TT::SlashEquals => OpCode::Div, _ => OpCode::Mod };
+                    let (y, r) = self.parse_expr(r)?;
+                    let x = self.scope.varapp(&mut self.arena, &alias.lexeme).map_err(|e| e.at(alias.span))?;
+                    (self.binary(op, x, y), r)
                 }
-                TT::Minus => {
-                    let (y, r) = self.parse_term(_r)?;
-                    let sub = DefEdge::new(OpCode::Sub);
-                    let (_, _) = (sub.add_def(&x), sub.add_def(&y));
-                    Ok((sub.peephole(&self.start), r))
+                TT::PlusPlus | TT::MinusMinus => {
+                    let op = match f.typ { TT::PlusPlus => OpCode::Add, _ => OpCode::Sub };
+                    let (x, one) = (self.scope.varapp(&mut self.arena, &alias.lexeme).map_err(|e| e.at(alias.span))?, self.con(Type::Int(1)));
+                    (self.binary(op, x, one), r)
                 }
-                _ => Ok((x, r)),
+                _ => return Err(self.mismatch("an assignment", Some(f))),
             },
+        };
+
+        Ok((self.scope.varupd(&mut self.arena, &alias.lexeme, expr).map_err(|e| e.at(alias.span))?, r))
+    }
+
+    // NB: shared by while and for loops, starting after the open paren (and a for loop's init).
+    //     the step of a for loop textually precedes the body, so it's skipped and parsed after the body
+    fn parse_loop<'a>(&mut self, tokens: &'a [Token], has_step: bool) -> Result<(NodeId, &'a [Token]), ParseError> {
+        // NB: the loop is in progress until end_loop sets its back edge, but is typed by its entry, so a loop
+        //     under dead control is dead. the head scope is kept aside, and the body starts with lazy bindings into it
+        let loop_ = self.node(OpCode::Loop, &[self.ctrl()]);
+        let loop_ = self.peephole(loop_);
+        self.set_ctrl(loop_);
+        let body = self.scope.dup(&mut self.arena, true);
+        let head = mem::replace(&mut self.scope, body);
+
+        let (pred, r) = self.parse_expr(tokens)?;
+        let (step, r) = match has_step {
+            true => {
+                let (_, r) = self.require(r, TT::PuncSemiColon)?;
+                (r, &r[Self::find_close(r)..])
+            }
+            false => (&r[..0], r),
+        };
+        // NB: the step runs to the header's close paren. a header that isn't closed is reported at its step
+        //     (while the init is in scope), which is otherwise parsed once, after the body
+        let stepped = has_step && step.first().is_some_and(|f| f.typ != TT::PuncRightParen);
+        if stepped && !matches!(r, [f, ..] if f.typ == TT::PuncRightParen) { self.parse_step(step)?; }
+        let (_, r) = self.require(r, TT::PuncRightParen)?;
+        let (left, right) = self.branch(pred);
+
+        self.set_ctrl(right);
+        let exit = self.scope.dup(&mut self.arena, false); // the exit sees the predicate's side effects
+        self.set_ctrl(left);
+        let (_, r) = self.parse_body(r)?;
+        if stepped { self.parse_step(step)?; }
+        let back = mem::replace(&mut self.scope, exit);
+
+        head.end_loop(&mut self.arena, back, &self.scope, self.start, self.opt);
+        Ok((loop_, r))
+    }
+
+    // the step of a for loop, which must be followed by the header's close paren
+    fn parse_step(&mut self, tokens: &[Token]) -> Result<(), ParseError> {
+        let (_, r) = self.parse_simple(tokens)?;
+        self.require(r, TT::PuncRightParen).map(|_| ())
+    }
+
+    // index of the close paren matching an already consumed open paren, or of the end of the statement
+    // (a `;` or brace) when it isn't closed
+    fn find_close(tokens: &[Token]) -> usize {
+        let mut depth = 0;
+        tokens.iter().position(|t| match t.typ {
+            TT::PuncLeftParen => { depth += 1; false }
+            TT::PuncRightParen => if depth == 0 { true } else { depth -= 1; false },
+            TT::PuncSemiColon | TT::PuncLeftBrace | TT::PuncRightBrace => depth == 0,
+            _ => false,
+        }).unwrap_or(tokens.len())
+    }
+
+    // NB: constants hang off of start
+    fn con(&mut self, typ: Type) -> NodeId {
+        let con = self.arena.new_constant(typ, self.start);
+        self.peephole(con)
+    }
+
+    // NB: division and modulus trap (on a zero divisor), so they can't float above the control they're
+    //     parsed under. that control is their last def, after the operands
+    fn binary(&mut self, op: OpCode, x: NodeId, y: NodeId) -> NodeId {
+        let node = match op {
+            OpCode::Div | OpCode::Mod => self.node(op, &[x, y, self.ctrl()]),
+            _ => self.node(op, &[x, y]),
+        };
+        self.peephole(node)
+    }
+
+    // splits control on pred, returning the (true, false) projections
+    fn branch(&mut self, pred: NodeId) -> (NodeId, NodeId) {
+        let branch = self.node(OpCode::If, &[self.ctrl(), pred]);
+        let branch = self.peephole(branch);
+        let (left, right) = (self.node(OpCode::Proj(0), &[branch]), self.node(OpCode::Proj(1), &[branch]));
+        (self.peephole(left), self.peephole(right))
+    }
+
+    fn parse_expr<'a>(&mut self, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
+        self.parse_binary(tokens, 0)
+    }
+
+    // NB: precedence climbing. the right operand only binds operators of strictly higher
+    //     precedence, so operators of the same precedence are folded into x (left associative)
+    fn parse_binary<'a>(&mut self, tokens: &'a [Token], min_prec: u8) -> Result<(NodeId, &'a [Token]), ParseError> {
+        let (mut x, mut r) = self.parse_unary(tokens)?;
+
+        while let [f, _r @ ..] = r {
+            let Some(&(_, prec, infix)) = INFIX.iter().find(|(tt, prec, _)| *tt == f.typ && *prec >= min_prec) else { break };
+            (x, r) = match infix {
+                Infix::And | Infix::Or => self.short_circuit(x, _r, matches!(infix, Infix::And), prec)?,
+                Infix::Node(op) => { let (y, r) = self.parse_binary(_r, prec + 1)?; (self.binary(op, x, y), r) }
+                Infix::Swapped(op) => { let (y, r) = self.parse_binary(_r, prec + 1)?; (self.binary(op, y, x), r) }
+            };
         }
+        Ok((x, r))
     }
 
-    fn parse_factor<'a>(&self, tokens: &'a [Token]) -> Result<(DefEdge, &'a [Token]), ParseError> {
-        let (x, r) = self.parse_atom(tokens)?;
+    // NB: the right operand of && (||) is only evaluated when the left is true (false), so the
+    //     operators lower to a branch on the left, with a phi merging the right (normalized
+    //     to a bool with != 0) and the short circuited result. constant left operands skip the branch.
+    //     expressions don't update bindings, so unlike if statements, only ctrl needs to be threaded
+    fn short_circuit<'a>(&mut self, x: NodeId, tokens: &'a [Token], and: bool, prec: u8) -> Result<(NodeId, &'a [Token]), ParseError> {
+        let short = self.con(Type::Int(if and { 0 } else { 1 }));
 
-        match r {
-            [] => panic!(),
-            [f, _r @ ..] => match f.typ {
-                TT::Star => {
-                    let (y, r) = self.parse_factor(_r)?;
-                    let mul = DefEdge::new(OpCode::Mul);
-                    let (_, _) = (mul.add_def(&x), mul.add_def(&y));
-                    Ok((mul.peephole(&self.start), r))
-                }
-                TT::Slash => {
-                    let (y, r) = self.parse_factor(_r)?;
-                    let div = DefEdge::new(OpCode::Div);
-                    let (_, _) = (div.add_def(&x), div.add_def(&y));
-                    Ok((div.peephole(&self.start), r))
-                }
-                _ => Ok((x, r)),
-            },
+        let x_type = self.arena.typ(x).clone();
+        if let (Type::Int(c), OptLevel::O1) = (x_type, self.opt) {
+            let (ctrl, live) = (self.ctrl(), (c != 0) == and);
+            if !live { let dead = self.con(Type::XCtrl); self.set_ctrl(dead); }
+            let (y, r) = self.parse_binary(tokens, prec + 1)?;
+            self.set_ctrl(ctrl);
+            return Ok((if live { let zero = self.con(Type::Int(0)); self.binary(OpCode::Ne, y, zero) } else { short }, r));
+        }
+
+        let (left, right) = self.branch(x);
+        let (eval, skip) = if and { (left, right) } else { (right, left) };
+        self.set_ctrl(eval);
+        let (y, r) = self.parse_binary(tokens, prec + 1)?;
+        let zero = self.con(Type::Int(0));
+        let y = self.binary(OpCode::Ne, y, zero);
+
+        let region = self.node(OpCode::Region, &[self.ctrl(), skip]);
+        let region = self.peephole(region);
+        self.set_ctrl(region);
+
+        let phi = self.node(OpCode::Phi, &[region, y, short]);
+        Ok((self.peephole(phi), r))
+    }
+
+    fn parse_unary<'a>(&mut self, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
+        match tokens {
+            [f, r @ ..] if matches!(f.typ, TT::Minus | TT::Bang | TT::Tilde) => {
+                let (x, r) = self.parse_unary(r)?;
+                let op = match f.typ { TT::Minus => OpCode::Neg, TT::Bang => OpCode::Not, _ => OpCode::Compl };
+                let unary = self.node(op, &[x]);
+                Ok((self.peephole(unary), r))
+            }
+            _ => self.parse_atom(tokens),
         }
     }
 
-    fn parse_atom<'a>(&self, tokens: &'a [Token]) -> Result<(DefEdge, &'a [Token]), ParseError> {
+    fn parse_atom<'a>(&mut self, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
         match tokens {
-            [] => Err(ParseError::Mismatch { expected: "".to_string(), actual: "".to_string() }),
+            [] => Err(self.mismatch("an expression", None)),
             [f, r @ ..] => match f.typ {
-                TT::LiteralInt => {
-                    let lit = DefEdge::new_constant(OpCode::Con, Type::Int(f.lexeme.parse().unwrap()));
-                    let _ = lit.add_def(&self.start);
-                    
-                    Ok((lit.peephole(&self.start), r))
+                TT::LiteralInt => Ok((self.con(Type::Int(int_literal(&f.lexeme).expect("lexer checks int literals"))), r)),
+                TT::LiteralChar => Ok((self.con(Type::Int(char_literal(&f.lexeme).expect("lexer checks char literals"))), r)),
+                TT::PuncLeftParen => {
+                    let (expr, r) = self.parse_expr(r)?;
+                    let (_, r) = self.require(r, TT::PuncRightParen)?;
+                    Ok((expr, r))
                 }
-                TT::Alias => {
-                    let expr = self.scope.varapp(&f.lexeme)?;
-                    Ok((expr,r))
+                TT::Alias => match r {
+                    [p, ..] if p.typ == TT::PuncLeftParen => self.parse_call(f, r),
+                    _ => Ok((self.scope.varapp(&mut self.arena, &f.lexeme).map_err(|e| e.at(f.span))?, r)),
                 },
-                t => Err(ParseError::Mismatch {
-                    expected: format!("expected: {:?} got: {:?}", TT::LiteralInt, t),
-                    actual: f.lexeme.to_owned(),
-                }),
+                _ => Err(self.mismatch("an expression", Some(f))),
             },
         }
     }
 
-    fn require<'a> (tokens: &'a [Token], tt: TT) -> Result<(&'a Token, &'a [Token]), ParseError> {
+    // NB: the call end projects the control and value flowing out of the callee
+    fn parse_call<'a>(&mut self, name: &Token, tokens: &'a [Token]) -> Result<(NodeId, &'a [Token]), ParseError> {
+        let (_, mut r) = self.require(tokens, TT::PuncLeftParen)?;
+        let mut args = Vec::new();
+        while let [f, ..] = r {
+            if f.typ == TT::PuncRightParen { break }
+            let (arg, _r) = self.parse_expr(if args.is_empty() { r } else { self.require(r, TT::PuncComma)?.1 })?;
+            args.push(arg);
+            r = _r;
+        }
+        let (_, r) = self.require(r, TT::PuncRightParen)?;
+
+        let fid = self.fid(&name.lexeme);
+        let call = self.node(OpCode::Call(fid), &[&[self.ctrl()], args.as_slice()].concat());
+        let call = self.peephole(call);
+        let end = self.node(OpCode::CallEnd, &[call]);
+        let end = self.peephole(end);
+
+        let (ctrl, value) = (self.node(OpCode::Proj(0), &[end]), self.node(OpCode::Proj(1), &[end]));
+        let ctrl = self.peephole(ctrl);
+        self.set_ctrl(ctrl);
+        self.calls.push((fid, call, end, args.len(), name.span));
+        Ok((self.peephole(value), r))
+    }
+
+    fn require<'a> (&self, tokens: &'a [Token], tt: TT) -> Result<(&'a Token, &'a [Token]), ParseError> {
         match tokens {
-            [] => Err(ParseError::Mismatch { expected: format!("expected: {:?} got: {:?}", tt, tokens), actual: "".to_string(),
-            }),
-            [f, r @ ..] => {
-                if f.typ == tt {
-                    Ok((f, r))
-                } else {
-                    Err(ParseError::Mismatch {
-                        expected: format!("expected: {:?} got: {:?}", tt, f),
-                        actual: f.lexeme.to_owned(),
-                    })
-                }
-            }
+            [f, r @ ..] if f.typ == tt => Ok((f, r)),
+            [f, ..] => Err(self.mismatch(&tt.to_string(), Some(f))),
+            [] => Err(self.mismatch(&tt.to_string(), None)),
+        }
+    }
+
+    // NB: a missing token is found at the end of the file
+    fn mismatch(&self, expected: &str, actual: Option<&Token>) -> ParseError {
+        match actual {
+            Some(t) => ParseError::Mismatch { expected: expected.to_owned(), actual: t.lexeme.to_owned(), span: t.span },
+            None => ParseError::Mismatch { expected: expected.to_owned(), actual: String::new(), span: self.eof },
         }
     }
 }
 
 #[derive(Clone, PartialEq, Debug)]
-pub struct Token { pub lexeme: String, pub typ: TT }
+pub struct Token { pub lexeme: String, pub typ: TT, pub span: Span }
+
+// NB: start and end are byte offsets into the (utf-8) source. line and col are 1-based, and point at the token's first char.
+//     src is the index of the source in its loader (0 when a single source is lexed)
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Span { pub start: usize, pub end: usize, pub line: usize, pub col: usize, pub src: usize }
 
 //  1. variations are explicitly typed. Collapsing categories like keywords
 //     into one variant will lose information since lexeme : String, which
 //     will produce redundant work for the parser during syntactic analysis
-//  2. non-tokens: comments, preprocessor directives, macros, whitespace
+//  2. non-tokens: comments, macros, whitespace. #use directives are tokens
+//     (the whole directive is the lexeme), resolved by the loader before parsing
 #[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TT {
-    LiteralInt, Alias, // introductions (values) RE: [0-9]+ and [a-zA-Z][a-zA-Z0-9]*
+    LiteralInt, LiteralChar, Alias, // introductions (values) RE: 0|[1-9][0-9]*|0[xX][0-9a-fA-F]+, '<char>' and [a-zA-Z_][a-zA-Z0-9_]*
     KeywordInt, KeywordChar, KeywordVoid, KeywordRet, KeywordIf, KeywordEls, KeywordFor, KeywordWhile, KeywordTrue, KeywordFalse, // keywords ⊂ identifiers
-    Plus, Minus, Star, Slash, LeftAngleBracket, RightAngleBracket, Equals, Bang, Amp, Bar, // eliminations (ops)
+    Plus, Minus, Star, Slash, Percent, LeftAngleBracket, RightAngleBracket, Equals, Bang, Tilde, Amp, Bar, // eliminations (ops)
+    LeftAngleBracketEquals, RightAngleBracketEquals, EqualsEquals, BangEquals, AmpAmp, BarBar, // comparisons and logic
+    PlusPlus, MinusMinus, PlusEquals, MinusEquals, StarEquals, SlashEquals, PercentEquals, // updates
     PuncLeftParen, PuncRightParen, PuncLeftBrace, PuncRightBrace, PuncSemiColon, PuncComma,// punctuation
+    UseLib, UseFile, // directives: #use <lib> and #use "file"
+}
+
+impl Display for TT {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { match self {
+        TT::LiteralInt => write!(f, "integer literal"), TT::LiteralChar => write!(f, "char literal"),
+        TT::Alias => write!(f, "identifier"),
+        TT::KeywordInt => write!(f, "`int`"), TT::KeywordChar => write!(f, "`char`"), TT::KeywordVoid => write!(f, "`void`"),
+        TT::KeywordRet => write!(f, "`return`"), TT::KeywordIf => write!(f, "`if`"), TT::KeywordEls => write!(f, "`else`"),
+        TT::KeywordFor => write!(f, "`for`"), TT::KeywordWhile => write!(f, "`while`"), TT::KeywordTrue => write!(f, "`true`"), TT::KeywordFalse => write!(f, "`false`"),
+        TT::UseLib | TT::UseFile => write!(f, "`#use`"),
+        tt => write!(f, "`{}`", PUNCT.iter().find(|(_, t)| t == tt).map(|(p, _)| *p).unwrap_or_default()),
+    }}
 }
 
 #[derive(Error, Debug)]
-pub enum LexError { #[error("(unknown token {unknown:?}")] UnknownToken { unknown: String } }
-
-fn lex(input: &[char]) -> Result<Vec<Token>, LexError> {
-    let cs = skip_ws(input);
-    // literals and identifiers have arbitrary length, operations and punctuations are single ASCII characters
-    match cs {
-        [] => Ok(vec![]),
-        [f, r @ ..] => match f {
-            '0'..='9' => scan_int(cs),
-            'a'..='z' | 'A'..='Z' => scan_id(cs),
-            '+' => { let t = Token { lexeme: String::from("+"), typ: TT::Plus }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '-' => { let t = Token { lexeme: String::from("-"), typ: TT::Minus }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '*' => { let t = Token { lexeme: String::from("*"), typ: TT::Star }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '/' => { let t = Token { lexeme: String::from("/"), typ: TT::Slash }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '<' => { let t = Token { lexeme: String::from("<"), typ: TT::LeftAngleBracket }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '>' => { let t = Token { lexeme: String::from(">"), typ: TT::RightAngleBracket }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '=' => { let t = Token { lexeme: String::from("="), typ: TT::Equals }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '!' => { let t = Token { lexeme: String::from("!"), typ: TT::Bang }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '&' => { let t = Token { lexeme: String::from("&"), typ: TT::Amp }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '|' => { let t = Token { lexeme: String::from("|"), typ: TT::Bar }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '(' => { let t = Token { lexeme: String::from("("), typ: TT::PuncLeftParen }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            ')' => { let t = Token { lexeme: String::from(")"), typ: TT::PuncRightParen }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '{' => { let t = Token { lexeme: String::from("{"), typ: TT::PuncLeftBrace }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            '}' => { let t = Token { lexeme: String::from("}"), typ: TT::PuncRightBrace }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            ';' => { let t = Token { lexeme: String::from(";"), typ: TT::PuncSemiColon }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            ',' => { let t = Token { lexeme: String::from(","), typ: TT::PuncComma }; Ok(iter::once(t).chain(lex(r)?).collect()) }
-            _ => Err(LexError::UnknownToken { unknown: f.to_string() }),
-        },
-    }
+pub enum LexError {
+    #[error("unknown token `{unknown}`")] UnknownToken { unknown: String, span: Span },
+    #[error("invalid literal `{literal}`")] InvalidLiteral { literal: String, span: Span },
+    #[error("integer literal `{literal}` is out of range for a 32-bit int")] OutOfRange { literal: String, span: Span },
+    #[error("unterminated block comment")] UnterminatedComment { span: Span },
+    #[error("invalid directive `{directive}`, expected `#use <lib>` or `#use \"file\"`")] InvalidDirective { directive: String, span: Span },
+    #[error("cannot resolve `#use` of `{name}`")] UnresolvedUse { name: String, span: Span },
+    #[error("source is not valid utf-8")] InvalidEncoding { span: Span },
 }
 
-fn scan_int(input: &[char]) -> Result<Vec<Token>, LexError> {
-    // scan_int calls skip_whitespace too to remain idempotent
-    let cs = skip_ws(input);
+// NB: operations and punctuations are one or two ASCII characters. two character
+//     operations come first, so they are matched before their one character prefix
+const PUNCT: &[(&str, TT)] = &[
+    ("++", TT::PlusPlus), ("--", TT::MinusMinus), ("+=", TT::PlusEquals), ("-=", TT::MinusEquals), ("*=", TT::StarEquals), ("/=", TT::SlashEquals), ("%=", TT::PercentEquals),
+    ("<=", TT::LeftAngleBracketEquals), (">=", TT::RightAngleBracketEquals), ("==", TT::EqualsEquals), ("!=", TT::BangEquals), ("&&", TT::AmpAmp), ("||", TT::BarBar),
+    ("+", TT::Plus), ("-", TT::Minus), ("*", TT::Star), ("/", TT::Slash), ("%", TT::Percent), ("<", TT::LeftAngleBracket), (">", TT::RightAngleBracket),
+    ("=", TT::Equals), ("!", TT::Bang), ("~", TT::Tilde), ("&", TT::Amp), ("|", TT::Bar),
+    ("(", TT::PuncLeftParen), (")", TT::PuncRightParen), ("{", TT::PuncLeftBrace), ("}", TT::PuncRightBrace), (";", TT::PuncSemiColon), (",", TT::PuncComma),
+];
 
-    match cs {
-        [] => Ok(vec![]),
-        [f, _r @ ..] => match f {
-            '0'..='9' => {
-                let i = _r.iter().take_while(|&&c| c.is_numeric()).count();
-                let f = cs[..=i].iter().collect::<String>();
-                let r = &cs[i + 1..];
-                let t = Token { lexeme: f, typ: TT::LiteralInt };
-                Ok(iter::once(t).chain(lex(r)?).collect())
+fn lex(input: &[char]) -> Result<Vec<Token>, Vec<LexError>> {
+    let (tokens, errs): (Vec<_>, Vec<_>) = Lexer::new(input, 0).partition(|t| t.is_ok());
+    if errs.is_empty() { Ok(tokens.into_iter().flatten().collect()) } else { Err(errs.into_iter().filter_map(|e| e.err()).collect()) }
+}
+
+// NB: single pass over the source. each token is scanned in time linear to its length, and the lexer
+//     continues past errors (skipping the offending chars) so all of them are reported
+struct Lexer<'a> { cs: &'a [char], src: usize, i: usize, byte: usize, line: usize, col: usize }
+impl<'a> Lexer<'a> {
+    fn new(cs: &'a [char], src: usize) -> Self { Self { cs, src, i: 0, byte: 0, line: 1, col: 1 } }
+
+    fn bump(&mut self) -> () {
+        let c = self.cs[self.i];
+        (self.i, self.byte) = (self.i + 1, self.byte + c.len_utf8());
+        if c == '\n' { (self.line, self.col) = (self.line + 1, 1) } else { self.col += 1 }
+    }
+
+    fn bump_while(&mut self, p: impl Fn(char) -> bool) -> () { while self.i < self.cs.len() && p(self.cs[self.i]) { self.bump() } }
+
+    // the span from a token's first char up to the lexer's position
+    fn span(&self, start: usize, line: usize, col: usize) -> Span { Span { start, end: self.byte, line, col, src: self.src } }
+
+    fn skip(&mut self) -> Result<(), LexError> {
+        loop {
+            self.bump_while(|c| c.is_whitespace());
+            match &self.cs[self.i..] {
+                ['/', '/', ..] => self.bump_while(|c| c != '\n'),
+                ['/', '*', ..] => self.skip_comment()?,
+                _ => return Ok(()),
             }
-            _ => Err(LexError::UnknownToken { unknown: f.to_string() }),
-        },
+        }
     }
-}
 
-// TODO: support identifiers with alpha*numeric* characters after first alphabetic
-fn scan_id(input: &[char]) -> Result<Vec<Token>, LexError> {
-    // scan_id calls skip_whitespace too to remain idempotent
-    let cs = skip_ws(input);
-
-    match cs {
-        [] => Ok(vec![]),
-        [f, r @ ..] => match f {
-            'a'..='z' => {
-                // Find the index where the alphabetic characters end
-                let i = r.iter().take_while(|&&c| c.is_alphabetic()).count();
-
-                let f = (cs[..=i].iter()).collect::<String>();
-                let new_r = &cs[i + 1..];
-
-                let keyword = match f.as_str() {
-                    "int" => Some(Token { lexeme: f.to_string(), typ: TT::KeywordInt }),
-                    "if" => Some(Token { lexeme: f.to_string(), typ: TT::KeywordIf }),
-                    "else" => Some(Token { lexeme: f.to_string(), typ: TT::KeywordEls }),
-                    "for" => Some(Token { lexeme: f.to_string(), typ: TT::KeywordFor }),
-                    "while" => Some(Token { lexeme: f.to_string(), typ: TT::KeywordWhile }),
-                    "return" => Some(Token { lexeme: f.to_string(), typ: TT::KeywordRet }),
-                    "true" => Some(Token { lexeme: f.to_string(), typ: TT::KeywordTrue }),
-                    "false" => Some(Token { lexeme: f.to_string(), typ: TT::KeywordFalse }),
-                    _ => None,
-                };
+    // NB: block comments nest in C0
+    fn skip_comment(&mut self) -> Result<(), LexError> {
+        let (start, line, col, mut depth) = (self.byte, self.line, self.col, 0);
+        loop {
+            match &self.cs[self.i..] {
+                ['/', '*', ..] => { self.bump(); self.bump(); depth += 1 },
+                ['*', '/', ..] => { self.bump(); self.bump(); depth -= 1; if depth == 0 { return Ok(()) } },
+                [_, ..] => self.bump(),
+                [] => return Err(LexError::UnterminatedComment { span: Span { start, end: start + 2, line, col, src: self.src } }),
+            }
+        }
+    }
 
-                let t = match keyword {
-                    Some(k) => k,
-                    None => Token { lexeme: f, typ: TT::Alias },
+    fn scan(&mut self) -> Result<TT, LexError> {
+        let (i, start, line, col) = (self.i, self.byte, self.line, self.col);
+        match self.cs[self.i] {
+            // NB: identifiers can't start with a digit, so a literal running into one is an error (not two tokens).
+            //     decimals have no leading zeros (that's octal in C)
+            '0'..='9' => {
+                let hex = matches!(&self.cs[i..], ['0', 'x' | 'X', ..]);
+                if hex { self.bump(); self.bump(); self.bump_while(|c| c.is_ascii_hexdigit()) } else { self.bump_while(|c| c.is_ascii_digit()) }
+                let malformed = self.i < self.cs.len() && is_id(self.cs[self.i]);
+                self.bump_while(is_id);
+                let literal = self.cs[i..self.i].iter().collect::<String>();
+                let span = self.span(start, line, col);
+                match literal.as_bytes() {
+                    [b'0', b'x' | b'X'] | [b'0', b'0'..=b'9', ..] => Err(LexError::InvalidLiteral { literal, span }),
+                    _ if malformed => Err(LexError::InvalidLiteral { literal, span }),
+                    _ if int_literal(&literal).is_none() => Err(LexError::OutOfRange { literal, span }),
+                    _ => Ok(TT::LiteralInt),
+                }
+            },
+            'a'..='z' | 'A'..='Z' | '_' => { self.bump_while(is_id); Ok(TT::Alias) },
+            // NB: a char literal is one (possibly escaped) char, and never spans lines
+            '\'' => {
+                self.bump();
+                if self.i < self.cs.len() && self.cs[self.i] == '\\' { self.bump() }
+                if self.i < self.cs.len() && self.cs[self.i] != '\n' { self.bump() }
+                if self.i < self.cs.len() && self.cs[self.i] == '\'' { self.bump() }
+                let literal = self.cs[i..self.i].iter().collect::<String>();
+                match char_literal(&literal) {
+                    Some(_) => Ok(TT::LiteralChar),
+                    None => Err(LexError::InvalidLiteral { literal, span: self.span(start, line, col) }),
+                }
+            },
+            // NB: directives take the rest of their line (less trailing whitespace)
+            '#' => {
+                let eol = self.cs[i..].iter().position(|c| *c == '\n').map_or(self.cs.len(), |n| i + n);
+                let end = self.cs[i..eol].iter().rposition(|c| !c.is_whitespace()).map_or(eol, |n| i + n + 1);
+                while self.i < end { self.bump() }
+                let directive = self.cs[i..end].iter().collect::<String>();
+                match directive.strip_prefix("#use").filter(|u| u.starts_with(char::is_whitespace)).map(str::trim_start) {
+                    Some(u) if u.len() > 2 && u.starts_with('<') && u.ends_with('>') => Ok(TT::UseLib),
+                    Some(u) if u.len() > 2 && u.starts_with('"') && u.ends_with('"') => Ok(TT::UseFile),
+                    _ => Err(LexError::InvalidDirective { directive, span: self.span(start, line, col) }),
+                }
+            },
+            f => {
+                let r = &self.cs[self.i..];
+                let Some((p, tt)) = PUNCT.iter().find(|(p, _)| p.len() <= r.len() && p.chars().zip(r).all(|(c, d)| c == *d)) else {
+                    self.bump();
+                    return Err(LexError::UnknownToken { unknown: f.to_string(), span: self.span(start, line, col) })
                 };
-                Ok(iter::once(t).chain(lex(new_r)?).collect())
+                for _ in 0..p.len() { self.bump() }
+                Ok(*tt)
+            },
+        }
+    }
+}
+
+impl Iterator for Lexer<'_> {
+    type Item = Result<Token, LexError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Err(e) = self.skip() { return Some(Err(e)) } // NB: an unterminated comment runs to the end of the source
+        if self.i == self.cs.len() { return None }
+
+        let (i, start, line, col) = (self.i, self.byte, self.line, self.col);
+        let typ = match self.scan() { Ok(typ) => typ, Err(e) => return Some(Err(e)) };
+        let lexeme = self.cs[i..self.i].iter().collect::<String>();
+        let typ = match (typ, lexeme.as_str()) {
+            (TT::Alias, "int") => TT::KeywordInt,
+            (TT::Alias, "char") => TT::KeywordChar,
+            (TT::Alias, "void") => TT::KeywordVoid,
+            (TT::Alias, "if") => TT::KeywordIf,
+            (TT::Alias, "else") => TT::KeywordEls,
+            (TT::Alias, "for") => TT::KeywordFor,
+            (TT::Alias, "while") => TT::KeywordWhile,
+            (TT::Alias, "return") => TT::KeywordRet,
+            (TT::Alias, "true") => TT::KeywordTrue,
+            (TT::Alias, "false") => TT::KeywordFalse,
+            (typ, _) => typ,
+        };
+        Some(Ok(Token { lexeme, typ, span: self.span(start, line, col) }))
+    }
+}
+
+// NB: C0 programs #use libraries (found on the search path, as headers first) and other files (relative
+//     to the using file). a used file's tokens take the place of its directive, and every file is included
+//     once, so diamonds and cycles of #use's are harmless. sources are indexed by their tokens' span.src
+pub struct Source { pub path: PathBuf, pub chars: Vec<char> }
+pub struct Loader { libs: Vec<PathBuf>, pub sources: Vec<Source>, included: HashSet<PathBuf> }
+impl Loader {
+    pub fn new(libs: Vec<PathBuf>) -> Self { Self { libs, sources: Vec::new(), included: HashSet::new() } }
+
+    pub fn lex(&mut self, path: &Path) -> Result<Vec<Token>, Vec<ParseError>> {
+        let (mut tokens, mut errs) = (Vec::new(), Vec::new());
+        if !self.include(path, &mut tokens, &mut errs) {
+            // NB: the unreadable file is still a source, so its error can be diagnosed
+            self.sources.push(Source { path: path.to_owned(), chars: Vec::new() });
+            let span = Span { start: 0, end: 0, line: 1, col: 1, src: self.sources.len() - 1 };
+            errs.push(LexError::UnresolvedUse { name: path.display().to_string(), span }.into());
+        }
+        if errs.is_empty() { Ok(tokens) } else { Err(errs) }
+    }
+
+    pub fn diagnose(&self, e: &ParseError) -> Diagnostic {
+        let source = &self.sources[e.span().src];
+        e.diagnose(&source.path.display().to_string(), &source.chars)
+    }
+
+    // lexes the file at path (unless it's already included), returning false if it can't be read.
+    // NB: a source that isn't utf-8 is reported at its first invalid byte, and isn't lexed
+    fn include(&mut self, path: &Path, tokens: &mut Vec<Token>, errs: &mut Vec<ParseError>) -> bool {
+        if !self.included.insert(fs::canonicalize(path).unwrap_or_else(|_| path.to_owned())) { return true }
+        let Ok(bytes) = fs::read(path) else { return false };
+        let src = self.sources.len();
+        let chars = match String::from_utf8(bytes) {
+            Ok(text) => text.chars().collect::<Vec<_>>(),
+            Err(e) => {
+                let (valid, bytes) = (e.utf8_error().valid_up_to(), e.into_bytes());
+                let prefix = str::from_utf8(&bytes[..valid]).expect("prefix is valid");
+                let (line, col) = (prefix.matches('\n').count() + 1, prefix.rsplit('\n').next().unwrap_or_default().chars().count() + 1);
+                errs.push(LexError::InvalidEncoding { span: Span { start: valid, end: valid + 1, line, col, src } }.into());
+                self.sources.push(Source { path: path.to_owned(), chars: String::from_utf8_lossy(&bytes).chars().collect() });
+                return true
+            },
+        };
+        self.sources.push(Source { path: path.to_owned(), chars: chars.clone() });
+
+        for t in Lexer::new(&chars, src) {
+            match t {
+                Ok(t) if matches!(t.typ, TT::UseLib | TT::UseFile) => {
+                    let found = self.resolve(&t, path).is_some_and(|p| self.include(&p, tokens, errs));
+                    if !found { errs.push(LexError::UnresolvedUse { name: used(&t).to_owned(), span: t.span }.into()) }
+                },
+                Ok(t) => tokens.push(t),
+                Err(e) => errs.push(e.into()),
             }
-            _ => Err(LexError::UnknownToken { unknown: f.to_string() }),
-        },
+        }
+        true
+    }
+
+    fn resolve(&self, t: &Token, from: &Path) -> Option<PathBuf> {
+        let name = used(t);
+        match t.typ {
+            TT::UseFile => Some(from.parent().unwrap_or(Path::new("")).join(name)),
+            _ => self.libs.iter().flat_map(|dir| [dir.join(format!("{name}.h0")), dir.join(format!("{name}.c0"))]).find(|p| p.is_file()),
+        }
+    }
+}
+
+fn is_id(c: char) -> bool { c.is_ascii_alphanumeric() || c == '_' }
+
+// NB: ints are 32-bit two's complement. decimals range up to 2^31 (which is only
+//     representable as -2^31), and hexadecimals up to 2^32-1 (their bit pattern)
+fn int_literal(lexeme: &str) -> Option<i32> {
+    match lexeme.strip_prefix("0x").or(lexeme.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().map(|v| v as i32),
+        None => lexeme.parse::<u32>().ok().filter(|v| *v <= 1 << 31).map(|v| v as i32),
     }
 }
 
-fn skip_ws(input: &[char]) -> &[char] { match input { [] => input, [f, r @ ..] => if f.is_whitespace() { skip_ws(r) } else { input } } }
+// NB: chars are ASCII, and their literals support C0's escape sequences
+fn char_literal(lexeme: &str) -> Option<i32> {
+    let c = match lexeme.strip_prefix('\'')?.strip_suffix('\'')?.chars().collect::<Vec<_>>().as_slice() {
+        ['\\', e] => match e {
+            'n' => '\n', 't' => '\t', 'v' => '\x0b', 'b' => '\x08', 'r' => '\r', 'f' => '\x0c', 'a' => '\x07', '0' => '\0',
+            '\\' | '\'' | '"' => *e,
+            _ => return None,
+        },
+        [c] if *c != '\\' && *c != '\'' => *c,
+        _ => return None,
+    };
+    c.is_ascii().then_some(c as i32)
+}
+
+// the library or file named by a #use directive, without its delimiters
+fn used(t: &Token) -> &str { let u = t.lexeme["#use".len()..].trim_start(); &u[1..u.len() - 1] }
 
 #[cfg(test)]
 mod test_parser {
-    use crate::son::{dumper, parser, utils::read_chars, OpCode};
+    use crate::son::{dumper, optimizer::{OptLevel, Type}, parser, utils::read_chars, OpCode};
     use std::{assert_matches::assert_matches, fs, path::Path};
     
-    const TEST_DIR: &str = "tests/arith";
+    const TEST_DIR: &str = "tests/c0";
 
     #[test]
     fn lit() {
-        let chars = read_chars(Path::new(&format!("{TEST_DIR}/lit.c")));
-        let graph = parser::parse(&chars).unwrap();
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/arith/con.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
         // let dot = dumper::dump_dot(&chars, &graph).unwrap();
         // println!("{dot}");
 
@@ -329,8 +878,8 @@ mod test_parser {
     }
 
     #[test] fn add_compound() {
-        let chars = read_chars(Path::new(&format!("{TEST_DIR}/add_compound.c")));
-        let graph = parser::parse(&chars).unwrap();
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/arith/add_compound.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
         // let dot = dumper::dump_dot(&chars, &graph).unwrap();
         // println!("{dot}");
 
@@ -338,28 +887,251 @@ mod test_parser {
         // assert_matches!(graph.borrow().defs[0].borrow().opcode, OpCode::Start);
         // insta::assert_debug_snapshot!(graph);
     }
+
+    #[test] fn lexical_scope() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/bindings/asnmt_lexical_scope.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        assert_eq!(graph.main().rets.len(), 1);
+    }
+
+    #[test] fn branch() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/control/branch.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        // the predicate is true, so the else arm's projection is dead control (but isn't folded)
+        let (a, rets) = (&graph.arena, &graph.main().rets);
+        assert_eq!(rets.len(), 2);
+        assert_matches!(a.op(a.def(rets[0], 0)), OpCode::Proj(0));
+        assert_matches!(a.op(a.def(rets[1], 0)), OpCode::Proj(1));
+        assert_eq!(*a.typ(a.def(rets[1], 0)), Type::XCtrl);
+        let branch = a.def(a.def(rets[0], 0), 0);
+        assert_matches!(a.op(branch), OpCode::If);
+        assert_eq!(*a.typ(branch), Type::Tup(vec![Type::Ctrl, Type::XCtrl]));
+    }
+
+    #[test] fn ifels_els() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/control/ifels_els.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        // the predicate is false, so only the else arm projects the branch
+        let a = &graph.arena;
+        let (x, y) = (a.def(graph.main().rets[0], 0), a.def(graph.main().rets[1], 0));
+        assert_eq!(*a.typ(x), Type::XCtrl);
+        assert_matches!(a.op(y), OpCode::Proj(1));
+        assert_eq!(*a.typ(y), Type::Ctrl);
+    }
+
+    #[test] fn if_const() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/control/if_const.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        // the dead arm's value never reaches the merge, so a folds to the live arm's
+        let arena = &graph.arena;
+        let a = arena.def(graph.main().rets[0], 1);
+        assert_matches!(arena.op(a), OpCode::Con);
+        assert_eq!(*arena.typ(a), Type::Int(2));
+    }
+
+    #[test] fn update_inc() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/bindings/asnmt_update_inc.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        assert_eq!(graph.main().rets.len(), 1);
+    }
+
+    #[test] fn update_incaccum() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/bindings/asnmt_update_incaccum.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        assert_eq!(*graph.arena.typ(graph.arena.def(graph.main().rets[0], 1)), Type::Int(10));
+    }
+
+    #[test] fn for_loop() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/control/for.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        let a = &graph.arena;
+        let n = a.def(graph.main().rets[0], 1);
+        assert_matches!(a.op(n), OpCode::Phi);
+        assert_matches!(a.op(a.def(n, 2)), OpCode::Add);
+    }
+
+    #[test] fn function() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/bindings/function.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        let a = &graph.arena;
+        let (f, value) = (graph.fun("f").unwrap(), a.def(graph.main().rets[0], 1));
+        assert_matches!(a.op(value), OpCode::Proj(1));
+        let end = a.def(value, 0);
+        assert_matches!(a.op(end), OpCode::CallEnd);
+        assert!(a.def(end, 1) == f.rets[0]);
+        assert!(a.def(f.fun, 0) == a.def(end, 0)); // the call is the callee's entry
+        assert_matches!(a.typ(a.def(f.parms[0], 1)), Type::Int(9));
+    }
+
+    #[test] fn dead_call() {
+        // calls under dead control never reach their callee, but are still checked against it
+        let f = "int f(int x) { return x; }";
+        for main in ["int main() { return 1; return f(2); }", "int main() { return 0 && f(2); }"] {
+            let chars = format!("{f} {main}").chars().collect::<Vec<_>>();
+            let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+            assert_eq!(graph.arena.defs(graph.fun("f").unwrap().fun).len(), 0);
+        }
+        let chars = format!("{f} int main() {{ return 1; return f(2, 3); }}").chars().collect::<Vec<_>>();
+        assert_eq!(parser::parse(&chars, OptLevel::O1).err().unwrap().iter().map(|e| e.to_string()).collect::<Vec<_>>(), ["`f` takes 1 argument but 2 arguments were supplied"]);
+    }
+
+    #[test] fn dead_loop() {
+        // a loop after a return is dead, and so is its body: the call in it never reaches its callee
+        let src = "int f(int x) { return x; } int main() { int y = 0; return 1; while (arg) { y = f(2); return y; } return y; }";
+        for opt in [OptLevel::O0, OptLevel::O1] {
+            let graph = parser::parse(&src.chars().collect::<Vec<_>>(), opt).unwrap();
+            let a = &graph.arena;
+            let loop_ = a.ids().find(|n| a.op(*n) == OpCode::Loop).unwrap();
+            assert_eq!(*a.typ(loop_), Type::XCtrl);
+            assert_eq!(a.defs(graph.fun("f").unwrap().fun).len(), 0);
+            assert!(graph.main().rets[1..].iter().all(|r| *a.typ(a.def(*r, 0)) == Type::XCtrl));
+        }
+    }
+
+    #[test] fn function_composition() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/bindings/function_composition.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        assert_eq!(graph.funs.len(), 4);
+        assert_eq!(graph.fun("f").unwrap().parms.len(), 2);
+    }
+
+    #[test] fn static_scope() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/bindings/static_scope.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        assert_eq!(graph.arena.defs(graph.fun("f").unwrap().fun).len(), 1);
+    }
+
+    #[test] fn dyn_scope() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/lexical/dyn_scope.c")));
+        assert!(parser::parse(&chars, OptLevel::O1).is_err());
+    }
+
+    #[test] fn while_loop() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/control/while.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        let a = &graph.arena;
+        let (exit, y) = (a.def(graph.main().rets[0], 0), a.def(graph.main().rets[0], 1));
+        assert_matches!(a.op(exit), OpCode::Proj(1));
+        assert_matches!(a.op(y), OpCode::Phi);
+        assert_matches!(a.op(a.def(y, 0)), OpCode::Loop);
+        assert_eq!(a.defs(a.def(y, 0)).len(), 2); // entry and back edge
+
+        // x is 0 on entry and on the back edge, so its phi is removed
+        let branch = a.def(exit, 0);
+        assert_matches!(a.op(a.def(branch, 1)), OpCode::Con);
+    }
+
+    #[test] fn if_arg() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/control/if_arg.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        // arg is unknown at compile time, so the branch survives and a is merged by a phi of 2 and 1
+        let arena = &graph.arena;
+        let a = arena.def(graph.main().rets[0], 1);
+        assert_matches!(arena.op(a), OpCode::Phi);
+        assert_eq!(*arena.typ(a), Type::range(1, 2));
+        let pred = arena.def(arena.def(arena.def(arena.def(a, 0), 0), 0), 1);
+        assert_matches!(arena.op(arena.def(pred, 0)), OpCode::Proj(1));
+    }
+
+    #[test] fn body_scope() {
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/bindings/asnmt_body_scope.c")));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+
+        // declarations in unbraced bodies are scoped to the body
+        assert_eq!(*graph.arena.typ(graph.arena.def(graph.main().rets[0], 1)), Type::Int(1));
+    }
+
+    #[test] fn use_once() {
+        let mut loader = parser::Loader::new(vec![format!("{TEST_DIR}/use/lib").into()]);
+        let graph = parser::parse_file(&mut loader, Path::new(&format!("{TEST_DIR}/use/main.c")), OptLevel::O1).unwrap();
+        assert_eq!(graph.funs.iter().map(|f| f.name.as_str()).collect::<Vec<_>>(), ["inc", "twice", "main"]);
+        assert_eq!(loader.sources.len(), 3); // arith is used by main.c and util.c, but included once
+    }
+
+    #[test] fn encoding() {
+        // spans are utf-8 byte offsets, while columns count chars
+        let mut loader = parser::Loader::new(vec![]);
+        let errs = parser::parse_file(&mut loader, Path::new(&format!("{TEST_DIR}/lexical/utf8.c")), OptLevel::O1).err().unwrap();
+        let d = loader.diagnose(&errs[0]);
+        assert_eq!((d.ident.as_str(), d.line, d.col, errs[0].span().start), ("@", 2, 25, 38));
+
+        // a source that isn't utf-8 is reported at its first invalid byte
+        let mut loader = parser::Loader::new(vec![]);
+        let errs = parser::parse_file(&mut loader, Path::new(&format!("{TEST_DIR}/lexical/latin1.c")), OptLevel::O1).err().unwrap();
+        assert_eq!(errs.iter().map(|e| loader.diagnose(e).to_string()).collect::<Vec<_>>(), ["\
+error: source is not valid utf-8
+ --> tests/c0/lexical/latin1.c:2:11
+  |
+2 |     // caf\u{fffd}
+  |           ^"]);
+    }
+
+    #[test] fn use_unresolved() {
+        let mut loader = parser::Loader::new(vec![]);
+        let errs = parser::parse_file(&mut loader, Path::new(&format!("{TEST_DIR}/use/missing.c")), OptLevel::O1).err().unwrap();
+        assert_eq!(errs.iter().map(|e| loader.diagnose(e).ident).collect::<Vec<_>>(), ["nowhere.c"]);
+
+        // without a loader, #use's can't be resolved
+        let chars = read_chars(Path::new(&format!("{TEST_DIR}/use/main.c")));
+        assert_eq!(parser::parse(&chars, OptLevel::O1).err().unwrap().len(), 2);
+    }
+}
+
+#[derive(Error, Debug)] pub enum ScopeError {
+    #[error("`{alias}` is already defined in this scope")] DoubleDefine { alias: String },
+    #[error("cannot find `{alias}` in this scope")] NotFound { alias: String },
+    #[error("no environment exists")] NoNvExists,
+    #[error("`{name}` takes {} but {} {} supplied", plural(*.expected, "argument"), plural(*.actual, "argument"), if *.actual == 1 { "was" } else { "were" })] ArityMismatch { name: String, expected: usize, actual: usize },
 }
 
-#[derive(Error, Debug)] pub enum ScopeError { #[error("double define")] DoubleDefine, #[error("not found")] NotFound, #[error("no environment exists")] NoNvExists }
+impl ScopeError {
+    // NB: scopes only know names, so the parser locates their errors
+    fn at(self, span: Span) -> ParseError { ParseError::ScopeError { err: self, span } }
+    fn ident(&self) -> &str { match self {
+        Self::DoubleDefine { alias } | Self::NotFound { alias } => alias,
+        Self::ArityMismatch { name, .. } => name,
+        Self::NoNvExists => "",
+    }}
+}
 // NB: scope *is neither* a control node nor data node. it *uses* a SoN node's
 //     def/use edges to keep track of liveliness. more specifically, the only
 //     edges used with the node inside the scope struct are def edges: ones
 //     that point to nodes that are expressions (in the case of C, just data nodes)
 //     that is, the scope's node has no uses.
-pub struct Scope { pub lookup: DefEdge, pub nvs: Vec<HashMap<String, usize>> }
+//     the current control is bound to CTRL, which is always the first def.
+//     the program's input is bound to ARG in the global nv, visible to every function
+pub struct Scope { pub lookup: NodeId, pub nvs: Vec<HashMap<String, usize>> }
+const CTRL: &str = "$ctrl";
+const ARG: &str = "arg";
 impl Scope {
-    fn new() -> Self { Self { lookup: DefEdge::new(OpCode::Scope), nvs: Vec::new() } }
+    fn new(arena: &mut Arena) -> Self { Self { lookup: arena.new_node(OpCode::Scope, &[]), nvs: Vec::new() } }
     fn push_nv(&mut self) -> () { self.nvs.push(HashMap::new()) }
-    fn pop_nv(&mut self) -> () { let _ = self.nvs.pop().unwrap(); }
-    fn varapp(&self, alias: &str) -> Result<DefEdge, ScopeError> {self.read_update(alias, ScopeOp::Read, self.nvs.len()-1)}
-    fn _varupd(&self, alias: &str, expr: DefEdge) -> Result<DefEdge, ScopeError> {self.read_update(alias, ScopeOp::Update(expr), self.nvs.len()-1)}
-    fn vardef(&mut self, alias: &str, expr: DefEdge) -> Result<(), ScopeError> {
+    // NB: only the innermost nv defines new variables, so its defs are always the tail of lookup's defs
+    fn pop_nv(&mut self, arena: &mut Arena) -> () {
+        let nv = self.nvs.pop().unwrap();
+        let n = nv.values().min().copied().unwrap_or(arena.defs(self.lookup).len());
+        while arena.defs(self.lookup).len() > n { arena.pop_def(self.lookup); }
+    }
+    // NB: closes every nv, so the scope no longer keeps the nodes it bound alive
+    pub fn close(&mut self, arena: &mut Arena) -> () { while !self.nvs.is_empty() { self.pop_nv(arena); } }
+    fn ctrl(&self, arena: &Arena) -> NodeId { arena.def(self.lookup, 0) }
+    fn set_ctrl(&self, arena: &mut Arena, ctrl: NodeId) -> () { arena.set_def(self.lookup, 0, ctrl) }
+    fn varapp(&self, arena: &mut Arena, alias: &str) -> Result<NodeId, ScopeError> {self.read_update(arena, alias, ScopeOp::Read, self.nvs.len()-1)}
+    fn varupd(&self, arena: &mut Arena, alias: &str, expr: NodeId) -> Result<NodeId, ScopeError> {self.read_update(arena, alias, ScopeOp::Update(expr), self.nvs.len()-1)}
+    fn vardef(&mut self, arena: &mut Arena, alias: &str, expr: NodeId) -> Result<(), ScopeError> {
         let cur_nv = self.nvs.last_mut().ok_or(ScopeError::NoNvExists)?;
         match cur_nv.contains_key(alias) {
-            true => Err(ScopeError::DoubleDefine),
+            true => Err(ScopeError::DoubleDefine { alias: alias.to_owned() }),
             false => {
-                self.lookup.add_def(&expr);
-                let i_def = self.lookup.borrow().defs.len()-1;
+                arena.add_def(self.lookup, expr);
+                let i_def = arena.defs(self.lookup).len()-1;
                 cur_nv.insert(alias.to_owned(), i_def);
                 Ok(())
             },
@@ -367,21 +1139,90 @@ impl Scope {
     }
 
     // shared read/update makes lazi phi creation easier ch8
-    fn read_update(&self, alias: &str, op: ScopeOp, level: usize ) -> Result<DefEdge, ScopeError> {
+    fn read_update(&self, arena: &mut Arena, alias: &str, op: ScopeOp, level: usize ) -> Result<NodeId, ScopeError> {
         let cur_nv = self.nvs.get(level).unwrap();
         match cur_nv.get(alias) {
-            None => if level == 0 { Err(ScopeError::NotFound) } else { self.read_update(alias, op, level-1) },
+            None => if level == 0 { Err(ScopeError::NotFound { alias: alias.to_owned() }) } else { self.read_update(arena, alias, op, level-1) },
             Some(i_def) => {
-                let expr = self.lookup.borrow().defs[*i_def].clone();
+                let expr = Self::force(arena, self.lookup, *i_def);
                 Ok(match op { ScopeOp::Read => expr, ScopeOp::Update(n) => {
-                    self.lookup.borrow_mut().defs[*i_def] = n; // updating std::vec calls drop on rc
-                    self.lookup.borrow().defs[*i_def].clone()
+                    arena.set_def(self.lookup, *i_def, n);
+                    n
                 },})
             },
         }
     }
+
+    // NB: a loop body's bindings point to the loop head's scope node until they are first read or updated.
+    //     forcing one creates the phi at the loop head (which forces the outer heads of nested loops first)
+    fn force(arena: &mut Arena, lookup: NodeId, i: usize) -> NodeId {
+        let def = arena.def(lookup, i);
+        if arena.op(def) != OpCode::Scope { return def }
+
+        let (head, loop_) = (def, arena.def(def, 0));
+        let cur = arena.def(head, i);
+        let forced = arena.op(cur) == OpCode::Phi && arena.def(cur, 0) == loop_;
+        let phi = if forced { cur } else {
+            let entry = Self::force(arena, head, i);
+            let phi = arena.new_node(OpCode::Phi, &[loop_, entry]);
+            arena.set_def(head, i, phi);
+            phi
+        };
+        arena.set_def(lookup, i, phi);
+        phi
+    }
+}
+enum ScopeOp { Read, Update(NodeId) }
+
+impl Scope {
+    // NB: the duplicate shares the same defs, so both scopes start out pointing to the same nodes.
+    //     a lazy duplicate instead points every binding to this scope's node (see force)
+    fn dup(&self, arena: &mut Arena, lazy: bool) -> Self {
+        let lookup = arena.new_node(OpCode::Scope, &[self.ctrl(arena)]);
+        let defs = arena.defs(self.lookup)[1..].to_vec();
+        for def in defs { arena.add_def(lookup, if lazy { self.lookup } else { def }); }
+        Self { lookup, nvs: self.nvs.clone() }
+    }
+
+    // merges two scopes from the arms of a branch into a region.
+    // each binding that differs between the two scopes gets a phi
+    fn merge(&mut self, arena: &mut Arena, other: Scope, start: NodeId, opt: OptLevel) -> NodeId {
+        let region = arena.new_node(OpCode::Region, &[self.ctrl(arena), other.ctrl(arena)]);
+        let region = arena.peephole_at(region, start, opt);
+        self.set_ctrl(arena, region);
+
+        let n = arena.defs(self.lookup).len();
+        for i in 1..n {
+            if arena.def(self.lookup, i) == arena.def(other.lookup, i) { continue }
+            let (x, y) = (Self::force(arena, self.lookup, i), Self::force(arena, other.lookup, i));
+            let phi = arena.new_node(OpCode::Phi, &[region, x, y]);
+            let phi = arena.peephole_at(phi, start, opt);
+            arena.set_def(self.lookup, i, phi);
+        }
+        region
+    }
+
+    // called on the loop head's scope once the body is parsed: sets the loop's back edge,
+    // completes the phis forced in the body, and removes the ones which turned out redundant
+    fn end_loop(&self, arena: &mut Arena, back: Scope, exit: &Scope, start: NodeId, opt: OptLevel) -> () {
+        let loop_ = self.ctrl(arena);
+        arena.add_def(loop_, back.ctrl(arena));
+
+        let n = arena.defs(self.lookup).len();
+        for i in 1..n {
+            let (def, phi) = (arena.def(back.lookup, i), arena.def(self.lookup, i));
+            if def != self.lookup { arena.add_def(phi, def); }
+            if arena.def(exit.lookup, i) == self.lookup { arena.set_def(exit.lookup, i, phi); }
+        }
+
+        for i in 1..n {
+            let phi = arena.def(self.lookup, i);
+            if arena.op(phi) != OpCode::Phi || arena.def(phi, 0) != loop_ { continue }
+            let peeped = arena.peephole_at(phi, start, opt);
+            if peeped != phi { arena.subsume(phi, peeped); }
+        }
+    }
 }
-enum ScopeOp { Read, Update(DefEdge) }
 
 // #[cfg(test)]
 // mod test_scope {
@@ -429,52 +1270,134 @@ mod test_lexer {
 
     // arithmetic
     #[test] fn lit() {
-        let chars = read_chars(Path::new("tests/arith/lit.c"));
+        let chars = read_chars(Path::new("tests/c0/arith/con.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
     #[test] fn add() {
-        let chars = read_chars(Path::new("tests/arith/add.c"));
+        let chars = read_chars(Path::new("tests/c0/arith/add.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
     #[test] fn add_compound() {
-        let chars = read_chars(Path::new("tests/arith/add_compound.c"));
+        let chars = read_chars(Path::new("tests/c0/arith/add_compound.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
     #[test] fn sub() {
-        let chars = read_chars(Path::new("tests/arith/sub.c"));
+        let chars = read_chars(Path::new("tests/c0/arith/sub.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
     #[test] fn mul() {
-        let chars = read_chars(Path::new("tests/arith/mul.c"));
+        let chars = read_chars(Path::new("tests/c0/arith/mul.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
     #[test] fn div() {
-        let chars = read_chars(Path::new("tests/arith/div.c"));
+        let chars = read_chars(Path::new("tests/c0/arith/div.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
 
     // bindings
     #[test] fn asnmt() {
-        let chars = read_chars(Path::new("tests/bindings/asnmt.c"));
+        let chars = read_chars(Path::new("tests/c0/bindings/asnmt.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
     #[test] fn composition() {
-        let chars = read_chars(Path::new("tests/bindings/asnmt_composition.c"));
+        let chars = read_chars(Path::new("tests/c0/bindings/asnmt_composition.c"));
+        let tokens = parser::lex(&chars).unwrap();
+        insta::assert_debug_snapshot!(tokens);
+    }
+    #[test] fn update_dec() {
+        let chars = read_chars(Path::new("tests/c0/bindings/asnmt_update_dec.c"));
+        let tokens = parser::lex(&chars).unwrap();
+        insta::assert_debug_snapshot!(tokens);
+    }
+    #[test] fn update_incaccum() {
+        let chars = read_chars(Path::new("tests/c0/bindings/asnmt_update_incaccum.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
 
     // control
     #[test] fn branch() {
-        let chars = read_chars(Path::new("tests/control/branch.c"));
+        let chars = read_chars(Path::new("tests/c0/control/branch.c"));
         let tokens = parser::lex(&chars).unwrap();
         insta::assert_debug_snapshot!(tokens);
     }
+    #[test] fn gteq() {
+        let chars = read_chars(Path::new("tests/c0/control/gteq_true.c"));
+        let tokens = parser::lex(&chars).unwrap();
+        insta::assert_debug_snapshot!(tokens);
+    }
+    #[test] fn neq() {
+        let chars = read_chars(Path::new("tests/c0/control/neq_true.c"));
+        let tokens = parser::lex(&chars).unwrap();
+        insta::assert_debug_snapshot!(tokens);
+    }
+    #[test] fn and() {
+        let chars = read_chars(Path::new("tests/c0/control/and_true.c"));
+        let tokens = parser::lex(&chars).unwrap();
+        insta::assert_debug_snapshot!(tokens);
+    }
+
+    // non-tokens
+    #[test] fn comments() {
+        let chars = read_chars(Path::new("tests/c0/lexical/comments.c"));
+        let tokens = parser::lex(&chars).unwrap();
+        let lexemes = tokens.iter().map(|t| t.lexeme.as_str()).collect::<Vec<_>>();
+        assert_eq!(lexemes, ["int", "main", "(", ")", "{", "return", "1", "/", "2", ";", "}"]);
+    }
+    #[test] fn directives() {
+        let chars = "#use <conio>\n#use   \"lib/util.c0\"  \nint".chars().collect::<Vec<_>>();
+        let tokens = parser::lex(&chars).unwrap();
+        assert_eq!(tokens.iter().map(|t| t.typ).collect::<Vec<_>>(), [parser::TT::UseLib, parser::TT::UseFile, parser::TT::KeywordInt]);
+        assert_eq!(tokens[1].lexeme, "#use   \"lib/util.c0\"");
+    }
+
+    // identifiers and literals
+    #[test] fn ids() {
+        let chars = "x1 my_var Foo _t char void".chars().collect::<Vec<_>>();
+        let tokens = parser::lex(&chars).unwrap();
+        assert_eq!(tokens.iter().map(|t| t.lexeme.as_str()).collect::<Vec<_>>(), ["x1", "my_var", "Foo", "_t", "char", "void"]);
+        assert_eq!(tokens[4..].iter().map(|t| t.typ).collect::<Vec<_>>(), [parser::TT::KeywordChar, parser::TT::KeywordVoid]);
+    }
+    #[test] fn literals() {
+        let chars = r"0 0x1F 0XfF 2147483648 0xFFFFFFFF 'a' '\n' '\''".chars().collect::<Vec<_>>();
+        let tokens = parser::lex(&chars).unwrap();
+        assert_eq!(tokens.iter().filter(|t| t.typ == parser::TT::LiteralInt).count(), 5);
+        assert_eq!(tokens.iter().filter(|t| t.typ == parser::TT::LiteralChar).count(), 3);
+        assert_eq!(parser::int_literal("2147483648"), Some(i32::MIN));
+        assert_eq!(parser::int_literal("0xFFFFFFFF"), Some(-1));
+        assert_eq!(parser::char_literal(r"'\n'"), Some(10));
+    }
+    #[test] fn bad_literals() {
+        let chars = r"2147483649 0x100000000 007 0x 1_a '\q'".chars().collect::<Vec<_>>();
+        let errs = parser::lex(&chars).unwrap_err();
+        assert_eq!(errs.iter().map(|e| e.to_string()).collect::<Vec<_>>(), [
+            "integer literal `2147483649` is out of range for a 32-bit int",
+            "integer literal `0x100000000` is out of range for a 32-bit int",
+            "invalid literal `007`",
+            "invalid literal `0x`",
+            "invalid literal `1_a`",
+            r"invalid literal `'\q'`",
+        ]);
+    }
+
+    // spans
+    #[test] fn span() {
+        let chars = "int main() {\n  return 8;\n}".chars().collect::<Vec<_>>();
+        let tokens = parser::lex(&chars).unwrap();
+        assert_eq!(tokens[5].span, parser::Span { start: 15, end: 21, line: 2, col: 3, src: 0 });
+        assert_eq!(tokens[6].span, parser::Span { start: 22, end: 23, line: 2, col: 10, src: 0 });
+    }
+    #[test] fn large() {
+        let src = format!("int main() {{\n{}  return x;\n}}", "  int x = 1 + 2;\n".repeat(100_000));
+        let tokens = parser::lex(&src.chars().collect::<Vec<_>>()).unwrap();
+        assert_eq!(tokens.len(), 5 + 7 * 100_000 + 4);
+        assert_eq!(tokens.last().unwrap().span.line, 100_003);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,387 @@
+use std::{collections::{BTreeSet, HashMap}, mem};
+use crate::son::{optimizer::Type, parser::Function, Arena, NodeId, OpCode};
+
+// NB: global code motion (see: Click, "Global Code Motion/Global Value Numbering", 1995)
+//     control nodes are pinned, and so are the nodes that depend on them: phis (to their region), parms
+//     (to their fun), the value projections of calls and divisions that may trap (to the control they trap
+//     under, which is their last def). every other node floats, only ordered by its defs and uses, so it's
+//     placed by scheduling it early (the deepest block, in the dominator tree, of its defs) and late (the lca of its uses, where
+//     a phi uses its input at the end of the matching predecessor), then picking the block between the two
+//     with the shallowest loop nesting, latest first
+
+// a basic block: a run of control nodes started by a fun, region, loop or projection of an if (or never), and
+// ended by an if (or never), a return, a jump to a region, or nothing (the exit of a never, which falls into stop). nodes lists the head, then the phis, then the scheduled nodes with
+// each node's defs in the block before it, then the terminator (if any). preds are in the order of a merge's
+// inputs (so a phi's i'th value flows in from its i'th pred), with inputs from unreachable control left out
+// as None. succs of an if (or never) are its live (true, false) arms
+pub struct Block { pub id: usize, pub nodes: Vec<NodeId>, pub preds: Vec<Option<usize>>, pub succs: Vec<usize>, pub idom: Option<usize>, pub depth: usize, pub loop_depth: usize }
+
+impl Block {
+    pub fn head(&self) -> NodeId { self.nodes[0] }
+}
+
+// the blocks of a function in reverse postorder, so the entry comes first and every block
+// comes after its dominator. NB: unreachable control (and the data only it uses) is left out. a graph rewritten
+// by sccp is scheduled once eliminate has removed its dead control
+pub fn schedule(arena: &Arena, f: &Function) -> Vec<Block> {
+    let mut cfg = Cfg::build(arena, f.fun);
+    cfg.dominators();
+    cfg.loops();
+    let (mut early, mut placed) = (HashMap::new(), HashMap::new());
+    for n in cfg.floating() { cfg.late(n, &mut early, &mut placed); }
+    cfg.order(placed)
+}
+
+struct Cfg<'a> { arena: &'a Arena, blocks: Vec<Block>, ctrl: HashMap<NodeId, usize>, pinned: BTreeSet<NodeId> }
+
+impl<'a> Cfg<'a> {
+    // NB: blocks are discovered depth first from the fun, then renumbered in reverse postorder
+    fn build(arena: &'a Arena, fun: NodeId) -> Self {
+        let (mut heads, mut ctrl, mut chains, mut succs) = (vec![fun], HashMap::new(), Vec::new(), Vec::new());
+        let mut i = 0;
+        while i < heads.len() {
+            let mut chain = vec![heads[i]];
+            ctrl.insert(heads[i], i);
+            let mut next = Vec::new();
+            loop {
+                let cur = *chain.last().unwrap();
+                if matches!(arena.op(cur), OpCode::Ret) { break }
+                let live = |u: NodeId| arena.is_cfg(u) && *arena.typ(u) != Type::XCtrl && !matches!(arena.op(u), OpCode::Fun(_) | OpCode::Stop);
+                let mut outs = arena.users(cur).filter(|&u| live(u)).collect::<Vec<_>>();
+                outs.sort_by_key(|&u| arena.op(u) != OpCode::Proj(0)); // NB: the true arm of an if first
+                match outs.first().map(|&o| arena.op(o)) {
+                    None => break,
+                    Some(OpCode::Region | OpCode::Loop) => { next.extend(outs); break },
+                    Some(OpCode::Proj(_)) if matches!(arena.op(cur), OpCode::If | OpCode::Never) => { next.extend(outs); break },
+                    Some(_) => { ctrl.insert(outs[0], i); chain.push(outs[0]); },
+                }
+            }
+            let mut out = Vec::new();
+            for n in next {
+                let known = heads.iter().position(|&h| h == n);
+                out.push(known.unwrap_or_else(|| { heads.push(n); heads.len() - 1 }));
+            }
+            chains.push(chain);
+            succs.push(out);
+            i += 1;
+        }
+
+        // NB: reverse postorder. successors are visited last to first, so the arms of an if are laid out in order
+        let (mut post, mut seen, mut stack) = (Vec::new(), vec![false; heads.len()], vec![(0, 0)]);
+        seen[0] = true;
+        while let Some((b, k)) = stack.pop() {
+            match succs[b].iter().rev().nth(k) {
+                Some(&s) => { stack.push((b, k + 1)); if !seen[s] { seen[s] = true; stack.push((s, 0)); } },
+                None => post.push(b),
+            }
+        }
+        let rpo = post.into_iter().rev().collect::<Vec<_>>();
+        let mut renumber = vec![0; heads.len()];
+        for (id, &b) in rpo.iter().enumerate() { renumber[b] = id; }
+
+        let mut blocks = rpo.iter().enumerate().map(|(id, &b)| {
+            let succs = succs[b].iter().map(|&s| renumber[s]).collect();
+            Block { id, nodes: chains[b].clone(), preds: Vec::new(), succs, idom: None, depth: 0, loop_depth: 0 }
+        }).collect::<Vec<_>>();
+        let ctrl = ctrl.into_iter().map(|(n, b)| (n, renumber[b])).collect::<HashMap<_, _>>();
+
+        // NB: a merge's preds follow its inputs, while the preds of any other block are the one jumping to it
+        for block in blocks.iter_mut() {
+            let head = block.head();
+            block.preds = match arena.op(head) {
+                OpCode::Region | OpCode::Loop => arena.defs(head).iter().map(|d| ctrl.get(d).copied()).collect(),
+                OpCode::Fun(_) => Vec::new(),
+                _ => vec![ctrl.get(&arena.def(head, 0)).copied()],
+            };
+        }
+
+        // NB: arg isn't pinned, since start (which isn't in any function) is available everywhere
+        let mut pinned = BTreeSet::new();
+        for &n in blocks.iter().flat_map(|b| b.nodes.iter()) {
+            for u in arena.users(n) {
+                let pin = match arena.op(u) { OpCode::Phi | OpCode::Parm(_) => true, OpCode::Proj(_) => !arena.is_cfg(u), _ => arena.traps(u) };
+                if pin { pinned.insert(u); }
+            }
+        }
+        Self { arena, blocks, ctrl, pinned }
+    }
+
+    // NB: the iterative algorithm of Cooper, Harvey and Kennedy, over blocks numbered in reverse postorder
+    fn dominators(&mut self) {
+        let mut idom = vec![None; self.blocks.len()];
+        idom[0] = Some(0);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for b in 1..self.blocks.len() {
+                let mut preds = self.blocks[b].preds.iter().flatten().copied().filter(|&p| idom[p].is_some());
+                let Some(first) = preds.next() else { continue };
+                let new = preds.fold(first, |x, y| Self::intersect(&idom, x, y));
+                if idom[b] != Some(new) { idom[b] = Some(new); changed = true; }
+            }
+        }
+        for (b, &d) in idom.iter().enumerate() {
+            self.blocks[b].idom = if b == 0 { None } else { d };
+            self.blocks[b].depth = self.blocks[b].idom.map_or(0, |d| self.blocks[d].depth + 1);
+        }
+    }
+
+    fn intersect(idom: &[Option<usize>], mut x: usize, mut y: usize) -> usize {
+        while x != y {
+            while x > y { x = idom[x].unwrap(); }
+            while y > x { y = idom[y].unwrap(); }
+        }
+        x
+    }
+
+    fn lca(&self, mut x: usize, mut y: usize) -> usize {
+        while self.blocks[x].depth > self.blocks[y].depth { x = self.blocks[x].idom.unwrap(); }
+        while self.blocks[y].depth > self.blocks[x].depth { y = self.blocks[y].idom.unwrap(); }
+        while x != y { (x, y) = (self.blocks[x].idom.unwrap(), self.blocks[y].idom.unwrap()); }
+        x
+    }
+
+    // NB: the body of a loop is every block reaching its back edge without passing through its head
+    fn loops(&mut self) {
+        for h in 0..self.blocks.len() {
+            if !matches!(self.arena.op(self.blocks[h].head()), OpCode::Loop) { continue }
+            let Some(Some(back)) = self.blocks[h].preds.get(1).copied() else { continue };
+            let (mut body, mut stack) = (vec![h], vec![back]);
+            while let Some(b) = stack.pop() {
+                if body.contains(&b) { continue }
+                body.push(b);
+                stack.extend(self.blocks[b].preds.iter().flatten().copied());
+            }
+            for b in body { self.blocks[b].loop_depth += 1; }
+        }
+    }
+
+    // NB: a pinned node that isn't control is pinned to its first def's block (a division, to its last)
+    fn block_of_pinned(&self, n: NodeId) -> usize {
+        if let Some(&b) = self.ctrl.get(&n) { return b }
+        let defs = self.arena.defs(n);
+        self.ctrl[if self.arena.traps(n) { defs.last().unwrap() } else { &defs[0] }]
+    }
+
+    // the floating nodes used by the function, found from the inputs of its pinned nodes.
+    // NB: a phi's input from an unreachable pred is never used
+    fn floating(&self) -> Vec<NodeId> {
+        let a = self.arena;
+        let mut roots = Vec::new();
+        for b in &self.blocks { roots.extend(b.nodes.iter().skip(1).flat_map(|&n| a.defs(n).iter().skip(1).copied())); }
+        for &p in self.pinned.iter().filter(|p| a.traps(**p)) { roots.extend(a.defs(p).iter().copied()); }
+        for &p in self.pinned.iter().filter(|p| a.op(**p) == OpCode::Phi) {
+            let region = self.ctrl[&a.def(p, 0)];
+            let live = &self.blocks[region].preds;
+            roots.extend(a.defs(p).iter().skip(1).zip(live).filter(|(_, pred)| pred.is_some()).map(|(&d, _)| d));
+        }
+
+        let mut seen = BTreeSet::new();
+        while let Some(n) = roots.pop() {
+            if a.is_cfg(n) || self.pinned.contains(&n) || seen.contains(&n) || matches!(a.op(n), OpCode::Start) { continue }
+            roots.extend(a.defs(n).iter().copied());
+            seen.insert(n);
+        }
+        seen.into_iter().collect()
+    }
+
+    // NB: early, late and emit walk the graph with explicit stacks (of a node and the next of its defs or uses
+    //     to visit), since data chains are as long as the program
+    fn early(&self, n: NodeId, early: &mut HashMap<NodeId, usize>) -> usize {
+        let a = self.arena;
+        let mut stack = vec![(n, 0)];
+        while let Some(&mut (m, ref mut i)) = stack.last_mut() {
+            if *i == 0 {
+                if early.contains_key(&m) { stack.pop(); continue }
+                let pinned = match a.op(m) {
+                    OpCode::Start => Some(0),
+                    _ if a.is_cfg(m) || self.pinned.contains(&m) => Some(self.block_of_pinned(m)),
+                    _ => None,
+                };
+                if let Some(b) = pinned { early.insert(m, b); stack.pop(); continue }
+            }
+            match a.defs(m).get(*i) {
+                Some(&d) => { *i += 1; if !early.contains_key(&d) { stack.push((d, 0)); } },
+                None => {
+                    let b = a.defs(m).iter().map(|d| early[d]).max_by_key(|&b| self.blocks[b].depth).unwrap_or(0);
+                    early.insert(m, b);
+                    stack.pop();
+                },
+            }
+        }
+        early[&n]
+    }
+
+    // NB: placed holds the chosen block of each floating node, which its defs are then placed above
+    fn late(&self, n: NodeId, early: &mut HashMap<NodeId, usize>, placed: &mut HashMap<NodeId, Option<usize>>) -> Option<usize> {
+        let a = self.arena;
+        if let Some(&b) = placed.get(&n) { return b }
+        if a.is_cfg(n) || self.pinned.contains(&n) { return Some(self.block_of_pinned(n)) }
+        placed.insert(n, None); // NB: data can't be cyclic without a phi, so this is only seen for dead nodes
+
+        let mut stack = vec![(n, a.users(n).collect::<Vec<_>>(), None)];
+        while let Some((m, users, lca)) = stack.last_mut() {
+            let m = *m;
+            let Some(&u) = users.last() else {
+                let best = lca.map(|late| self.hoist(m, late, early));
+                placed.insert(m, best);
+                stack.pop();
+                continue
+            };
+            let uses = match a.op(u) {
+                OpCode::Scope => Vec::new(),
+                OpCode::Phi if self.pinned.contains(&u) => {
+                    let region = self.ctrl[&a.def(u, 0)];
+                    a.defs(u).iter().skip(1).zip(&self.blocks[region].preds).filter(|(d, _)| **d == m).filter_map(|(_, p)| *p).collect()
+                },
+                // NB: uses by unreachable control (or another function) don't count
+                OpCode::Phi | OpCode::Parm(_) if !self.pinned.contains(&u) => Vec::new(),
+                _ if a.is_cfg(u) && !self.ctrl.contains_key(&u) => Vec::new(),
+                _ if a.is_cfg(u) || self.pinned.contains(&u) => vec![self.block_of_pinned(u)],
+                _ => match placed.get(&u) {
+                    Some(b) => b.iter().copied().collect(),
+                    None => { placed.insert(u, None); stack.push((u, a.users(u).collect(), None)); continue },
+                },
+            };
+            users.pop();
+            for b in uses { *lca = Some(lca.map_or(b, |l| self.lca(l, b))); }
+        }
+        placed[&n]
+    }
+
+    // NB: the shallowest loop nesting between late and early, preferring later blocks
+    fn hoist(&self, n: NodeId, late: usize, early: &mut HashMap<NodeId, usize>) -> usize {
+        let early = self.early(n, early);
+        let (mut cur, mut best) = (late, late);
+        while cur != early {
+            cur = self.blocks[cur].idom.expect("early dominates late");
+            if self.blocks[cur].loop_depth < self.blocks[best].loop_depth { best = cur; }
+        }
+        best
+    }
+
+    fn order(mut self, placed: HashMap<NodeId, Option<usize>>) -> Vec<Block> {
+        let a = self.arena;
+        let mut members = vec![BTreeSet::new(); self.blocks.len()];
+        for &p in &self.pinned { members[self.block_of_pinned(p)].insert(p); }
+        for n in self.floating() {
+            if let Some(Some(b)) = placed.get(&n) { members[*b].insert(n); }
+        }
+
+        for (b, members) in members.into_iter().enumerate() {
+            let chain = mem::take(&mut self.blocks[b].nodes);
+            let (head, rest) = (chain[0], &chain[1..]);
+            let terminator = rest.last().copied().filter(|&t| matches!(a.op(t), OpCode::If | OpCode::Never | OpCode::Ret));
+            let mut scheduled = vec![head];
+            scheduled.extend(members.iter().copied().filter(|&n| matches!(a.op(n), OpCode::Phi | OpCode::Parm(_))));
+
+            // NB: depth first over the block's nodes by id, emitting each node's defs in the block before it
+            let mut pending = members;
+            pending.extend(rest.iter().copied());
+            for n in &scheduled { pending.remove(n); }
+            if let Some(t) = &terminator { pending.remove(t); }
+            while let Some(n) = pending.pop_first() { self.emit(n, &mut pending, &mut scheduled); }
+            scheduled.extend(terminator);
+            self.blocks[b].nodes = scheduled;
+        }
+        self.blocks
+    }
+
+    fn emit(&self, n: NodeId, pending: &mut BTreeSet<NodeId>, scheduled: &mut Vec<NodeId>) {
+        let mut stack = vec![(n, 0)];
+        while let Some(&mut (m, ref mut i)) = stack.last_mut() {
+            match self.arena.defs(m).get(*i) {
+                Some(&d) => { *i += 1; if pending.remove(&d) { stack.push((d, 0)); } },
+                None => { scheduled.push(m); stack.pop(); },
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test_scheduler {
+    use std::{assert_matches::assert_matches, collections::HashMap, fs, path::Path};
+    use crate::son::{optimizer::OptLevel, parser::{self, ParseResult}, scheduler::{schedule, Block}, utils::read_chars, Arena, OpCode};
+
+    fn blocks(file: &str) -> (ParseResult, Vec<Block>) {
+        let chars = read_chars(Path::new(file));
+        let graph = parser::parse(&chars, OptLevel::O1).unwrap();
+        let blocks = schedule(&graph.arena, graph.main());
+        (graph, blocks)
+    }
+
+    fn dominates(blocks: &[Block], a: usize, mut b: usize) -> bool {
+        loop {
+            if a == b { return true }
+            match blocks[b].idom { Some(d) => b = d, None => return false }
+        }
+    }
+
+    // every node is scheduled once, after its defs: earlier in its block, or in a dominating block
+    // (for a phi, one dominating the matching pred)
+    fn valid(arena: &Arena, blocks: &[Block]) {
+        let mut at = HashMap::new();
+        for b in blocks { for (i, &n) in b.nodes.iter().enumerate() { assert!(at.insert(n, (b.id, i)).is_none()); } }
+        for b in blocks {
+            for (i, &n) in b.nodes.iter().enumerate() {
+                for (k, &d) in arena.defs(n).iter().enumerate() {
+                    let Some(&(db, di)) = at.get(&d) else { continue };
+                    match arena.op(n) {
+                        OpCode::Phi if k > 0 => { if let Some(p) = blocks[b.id].preds[k - 1] { assert!(dominates(blocks, db, p)); } },
+                        OpCode::Phi | OpCode::Region | OpCode::Loop => (),
+                        _ => assert!(if db == b.id { di < i } else { dominates(blocks, db, b.id) }, "{:?} before {:?}", arena.at(d), arena.at(n)),
+                    }
+                }
+            }
+        }
+    }
+
+    #[test] fn all_valid() {
+        for dir in ["tests/c0/arith", "tests/c0/bindings", "tests/c0/control"] {
+            for entry in fs::read_dir(dir).unwrap() {
+                let path = entry.unwrap().path();
+                let chars = read_chars(&path);
+                let Ok(graph) = parser::parse(&chars, OptLevel::O1) else { continue };
+                for f in &graph.funs { valid(&graph.arena, &schedule(&graph.arena, f)); }
+            }
+        }
+    }
+
+    #[test] fn straight() {
+        let (graph, blocks) = blocks("tests/c0/arith/mult_add_precedence_arg.c");
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].nodes.iter().map(|&n| format!("{:?}", graph.arena.at(n))).collect::<Vec<_>>(), ["Fun0", "#1", "132", "*", "91", "+", "Ret"]);
+    }
+
+    #[test] fn diamond() {
+        let (graph, blocks) = blocks("tests/c0/control/if_arg.c");
+        valid(&graph.arena, &blocks);
+        assert_eq!(blocks.len(), 4);
+        assert_eq!(blocks[0].succs, [1, 2]);
+        assert_matches!(graph.arena.op(blocks[3].head()), OpCode::Region);
+        assert_eq!(blocks[3].idom, Some(0));
+        assert_matches!(graph.arena.op(blocks[3].nodes[1]), OpCode::Phi);
+    }
+
+    #[test] fn hoist() {
+        // arg * 3 is computed once, before the loop, while n + arg * 3 stays in it
+        let (graph, blocks) = blocks("tests/c0/control/while_hoist.c");
+        valid(&graph.arena, &blocks);
+        let op = |n| graph.arena.op(n);
+        let block_of = |o: OpCode| blocks.iter().find(|b| b.nodes.iter().any(|&n| op(n) == o)).unwrap();
+        assert_eq!(block_of(OpCode::Loop).loop_depth, 1);
+        assert_eq!(block_of(OpCode::Mul).loop_depth, 0);
+        assert_eq!(block_of(OpCode::Add).loop_depth, 1);
+        assert!(blocks.iter().any(|b| b.loop_depth == 0 && b.nodes.iter().any(|&n| op(n) == OpCode::Ret)));
+    }
+
+    #[test] fn guard() {
+        // 100 / d is loop invariant, but stays under the d != 0 that guards it
+        let (graph, blocks) = blocks("tests/c0/control/while_guard.c");
+        valid(&graph.arena, &blocks);
+        let op = |n| graph.arena.op(n);
+        let div = blocks.iter().find(|b| b.nodes.iter().any(|&n| op(n) == OpCode::Div)).unwrap();
+        assert_eq!(div.loop_depth, 1);
+        assert_matches!(op(div.head()), OpCode::Proj(0));
+    }
+}
@@ -1,5 +1,5 @@
 use std::{fs::read, path::Path};
 
 pub fn read_chars(path: &Path) -> Vec<char> {
-    read(path).expect("file dne").iter().map(|b| *b as char).collect::<Vec<_>>()
+    String::from_utf8(read(path).expect("file dne")).expect("source is utf-8").chars().collect::<Vec<_>>()
 }
\ No newline at end of file
@@ -0,0 +1,5 @@
+int main() {
+  int a = arg / 7;
+  int b = arg / 7;
+  return a * b;
+}
@@ -0,0 +1,4 @@
+int main() {
+  int x = arg * 3;
+  return (x + arg) - (arg + x);
+}
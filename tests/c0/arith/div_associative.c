@@ -0,0 +1,3 @@
+int main() {
+  return 100 / 10 / 5;
+}
\ No newline at end of file
@@ -0,0 +1,3 @@
+int main() {
+  return (arg + 0) * 1 - 0;
+}
@@ -0,0 +1,3 @@
+int main() {
+  return 9 * 10 + 11 * arg * 12 + 1;
+}
@@ -0,0 +1,3 @@
+int main() {
+    return 0x7FFFFFFF * 2 - -2147483648;
+}
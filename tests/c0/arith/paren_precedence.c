@@ -0,0 +1,3 @@
+int main() {
+  return (9 + 10) * 11;
+}
\ No newline at end of file
@@ -0,0 +1,3 @@
+int main() {
+  return 1 + arg + 2 + arg + 3;
+}
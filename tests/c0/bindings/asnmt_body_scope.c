@@ -0,0 +1,7 @@
+int main() {
+    int x = 1;
+    if (arg) int x = 2;
+    else int y = 3;
+    while (x < 0) int z = 4;
+    return x;
+}
@@ -0,0 +1,9 @@
+int main() {
+    int a = 0;
+    if (arg * 0 + 1) {
+        a = 2;
+    } else {
+        a = 1;
+    }
+    return a;
+}
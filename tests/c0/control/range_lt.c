@@ -0,0 +1,7 @@
+int main() {
+    int a = 1;
+    if (arg == 7) {
+        a = 2;
+    }
+    return a < 3;
+}
@@ -0,0 +1,9 @@
+int main() {
+    int x = 1;
+    int i = 0;
+    while (i < arg) {
+        if (x != 1) x = 2;
+        i = i + 1;
+    }
+    return x;
+}
@@ -0,0 +1,7 @@
+int main() {
+    int x = arg;
+    while (1) {
+        x = x + 1;
+    }
+    return x;
+}
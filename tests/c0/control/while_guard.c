@@ -0,0 +1,12 @@
+int main() {
+    int d = arg;
+    int s = 0;
+    int i = 0;
+    while (i < 10) {
+        if (d != 0) {
+            s = s + 100 / d;
+        }
+        i = i + 1;
+    }
+    return s;
+}
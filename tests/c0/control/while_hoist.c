@@ -0,0 +1,9 @@
+int main() {
+    int n = 0;
+    int i = 0;
+    while (i < 10) {
+        n = n + arg * 3;
+        i = i + 1;
+    }
+    return n;
+}
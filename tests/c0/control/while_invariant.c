@@ -0,0 +1,9 @@
+int main() {
+    int x = 1;
+    int y = 0;
+    while (arg < 10) {
+        arg = arg + 1;
+        y = x + 2;
+    }
+    return y;
+}
@@ -0,0 +1,4 @@
+// line comment
+int main() { /* block /* nested */ comment */
+    return 1 / 2; // trailing
+}
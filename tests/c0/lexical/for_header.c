@@ -0,0 +1,5 @@
+int main() {
+    int x = 0;
+    for (int i = 0; i < 10; i++ x += i;
+    return y;
+}
@@ -0,0 +1,4 @@
+int main() {
+    // caf
+    return 0;
+}
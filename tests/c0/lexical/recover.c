@@ -0,0 +1,6 @@
+int main() {
+    int x = ;
+    int y = 1;
+    y = z;
+    if (y) { y = 2; x = 1 }
+    return y +;
@@ -0,0 +1,3 @@
+int main() {
+    /* héllo */ return 0@1;
+}
@@ -0,0 +1,7 @@
+#use <arith>
+#use "util.c"
+
+// twice is defined in util.c, and inc in the arith library
+int main() {
+    return twice(inc(arg));
+}
@@ -0,0 +1,5 @@
+#use "nowhere.c"
+
+int main() {
+    return 0;
+}
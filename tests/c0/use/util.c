@@ -0,0 +1,6 @@
+#use <arith>
+
+/* arith is used again, but only included once */
+int twice(int x) {
+    return x + x;
+}